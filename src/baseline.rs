@@ -0,0 +1,157 @@
+use crate::output;
+use anyhow::{bail, Context};
+use ecr_dump::images::ImageWithManifests;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+/// The images in a previous dump, keyed by `(repository, digest)`, with their tags.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    images: HashMap<(String, String), BTreeSet<String>>,
+    seen: HashSet<(String, String)>,
+}
+
+#[derive(Deserialize)]
+struct BaselineRecord {
+    image: Option<BaselineImage>,
+    repository: Option<IgnoredAny>,
+    layer_stats: Option<IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+struct BaselineImage {
+    repository_name: String,
+    manifest_digest: String,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+}
+
+/// An image that was in the baseline but not in this run, as written to `--removed-file`.
+#[derive(Serialize)]
+struct RemovedImage<'a> {
+    repository_name: &'a str,
+    manifest_digest: &'a str,
+}
+
+impl Baseline {
+    /// Read the image records of a JSONL or JSON array dump, decompressing `.gz` and
+    /// `.zst` files. Repository records are ignored.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut baseline = Self::default();
+        output::read_records(path, |number, record: anyhow::Result<BaselineRecord>| {
+            let record = record.ok().filter(|record| {
+                record.image.is_some()
+                    || record.repository.is_some()
+                    || record.layer_stats.is_some()
+            });
+            let Some(record) = record else {
+                bail!("{} record {number}: not a full dump record", path.display());
+            };
+            if let Some(image) = record.image {
+                baseline
+                    .images
+                    .insert((image.repository_name, image.manifest_digest), image.tags);
+            }
+            Ok(())
+        })?;
+        Ok(baseline)
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    /// Mark `image` as still present and return whether it can be skipped: it was in the
+    /// baseline and, if `compare_tags` is set, its tags haven't changed.
+    pub fn is_unchanged(&mut self, image: &ImageWithManifests, compare_tags: bool) -> bool {
+        let key = (
+            image.image.repository_name.clone(),
+            image.image.manifest_digest.clone(),
+        );
+        let unchanged = self.images.get(&key).is_some_and(|tags| {
+            !compare_tags
+                || tags.len() == image.image.image_tags.len()
+                    && image.image.image_tags.iter().all(|tag| tags.contains(tag))
+        });
+        self.seen.insert(key);
+        unchanged
+    }
+
+    /// Write every baseline image not seen in this run as JSONL, sorted by repository
+    /// then digest. Images in `incomplete` repositories are left out, since they may
+    /// simply not have been fetched. Returns how many were written.
+    pub async fn write_removed(
+        &self,
+        path: &Path,
+        incomplete: &HashSet<String>,
+    ) -> anyhow::Result<usize> {
+        let mut removed: Vec<_> = self
+            .images
+            .keys()
+            .filter(|key| !self.seen.contains(*key) && !incomplete.contains(&key.0))
+            .collect();
+        removed.sort();
+        let mut out = Vec::new();
+        for (repository_name, manifest_digest) in &removed {
+            serde_json::to_writer(
+                &mut out,
+                &RemovedImage {
+                    repository_name,
+                    manifest_digest,
+                },
+            )?;
+            out.push(b'\n');
+        }
+        tokio::fs::write(path, out)
+            .await
+            .with_context(|| format!("Writing removed images to {}", path.display()))?;
+        Ok(removed.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_compressed_dumps() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = concat!(
+            r#"{"repository":{"repository_name":"app","image_count":2}}"#,
+            "\n",
+            r#"{"image":{"repository_name":"app","manifest_digest":"sha256:a","tags":["v1"]}}"#,
+            "\n",
+            r#"{"image":{"repository_name":"app","manifest_digest":"sha256:b"}}"#,
+            "\n",
+        );
+        let zstd = dir.path().join("baseline.jsonl.zst");
+        std::fs::write(&zstd, zstd::encode_all(lines.as_bytes(), 3).unwrap()).unwrap();
+        assert_eq!(Baseline::load(&zstd).unwrap().len(), 2);
+
+        let gzip = dir.path().join("baseline.jsonl.gz");
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+        std::io::Write::write_all(&mut encoder, lines.as_bytes()).unwrap();
+        std::fs::write(&gzip, encoder.finish().unwrap()).unwrap();
+        assert_eq!(Baseline::load(&gzip).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn loads_json_arrays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        let array = concat!(
+            "[\n",
+            r#"{"repository":{"repository_name":"app","image_count":1}},"#,
+            "\n",
+            r#"{"image":{"repository_name":"app","manifest_digest":"sha256:a","tags":["v1"]}}"#,
+            "\n]\n",
+        );
+        std::fs::write(&path, array).unwrap();
+        assert_eq!(Baseline::load(&path).unwrap().len(), 1);
+
+        std::fs::write(&path, r#"[{"digest":"sha256:a"}]"#).unwrap();
+        assert!(Baseline::load(&path).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{bail, Context};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::{debug, trace, warn};
+
+/// Marks a blob still being written, so eviction leaves it alone.
+const TMP_MARKER: &str = ".tmp-";
+
+/// On-disk cache of registry blobs, keyed by digest.
+///
+/// Blobs are content addressed so cached entries never go stale, and `sha256` blobs are
+/// checked against their digest when written and read. The cache grows by the size of
+/// every distinct blob fetched; set `max_bytes` to bound it, in which case the least
+/// recently written blobs are evicted once an insert takes it over the limit, down to
+/// 90% of it.
+#[derive(Debug, Clone)]
+pub struct BlobCache {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+    /// Bytes in the cache, counted on the first insert and kept up to date after, shared
+    /// by every clone.
+    size: Arc<Mutex<Option<u64>>>,
+    evicting: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl BlobCache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: Option<u64>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            size: Arc::default(),
+            evicting: Arc::default(),
+        }
+    }
+
+    fn path(&self, digest: &str) -> anyhow::Result<PathBuf> {
+        let Some((algorithm, hex)) = digest.split_once(':') else {
+            bail!("Invalid blob digest {digest:?}");
+        };
+        let valid = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid(algorithm) || !valid(hex) {
+            bail!("Invalid blob digest {digest:?}");
+        }
+        Ok(self.dir.join(algorithm).join(hex))
+    }
+
+    /// Whether `data` hashes to `digest`. Only `sha256` digests can be checked; others
+    /// are trusted.
+    fn matches_digest(digest: &str, data: &[u8]) -> bool {
+        match digest.strip_prefix("sha256:") {
+            Some(expected) => format!("{:x}", Sha256::digest(data)) == expected,
+            None => true,
+        }
+    }
+
+    /// A cached blob that no longer matches its digest is removed and reported as a miss.
+    pub async fn get(&self, digest: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let path = self.path(digest)?;
+        match tokio::fs::read(&path).await {
+            Ok(data) if Self::matches_digest(digest, &data) => {
+                trace!("Blob cache hit for {digest}");
+                Ok(Some(data))
+            }
+            Ok(_) => {
+                warn!("Cached blob {digest} is corrupt, fetching it again");
+                match tokio::fs::remove_file(&path).await {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        return Err(e).with_context(|| format!("Removing cached blob {digest}"))
+                    }
+                    _ => {}
+                }
+                Ok(None)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Reading cached blob {digest}")),
+        }
+    }
+
+    pub async fn put(&self, digest: &str, data: &[u8]) -> anyhow::Result<()> {
+        if !Self::matches_digest(digest, data) {
+            bail!("Not caching blob {digest}: its content has a different digest");
+        }
+        let path = self.path(digest)?;
+        let parent = path.parent().expect("blob path has a parent");
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Creating blob cache directory {}", parent.display()))?;
+        // Write to a temporary file first so concurrent readers never see a partial blob.
+        // The name is unique to this write, so concurrent writers of the same blob, from
+        // this process or another, don't clobber each other.
+        let mut tmp_name = path.file_name().expect("blob path has a name").to_owned();
+        tmp_name.push(format!(
+            "{TMP_MARKER}{}-{:016x}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        let tmp_path = path.with_file_name(tmp_name);
+        tokio::fs::write(&tmp_path, data)
+            .await
+            .with_context(|| format!("Caching blob {digest}"))?;
+        if let Err(e) = tokio::fs::rename(&tmp_path, &path).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e).with_context(|| format!("Caching blob {digest}"));
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            let size = match self.added(data.len() as u64) {
+                Some(size) => size,
+                None => self.count().await?,
+            };
+            if size > max_bytes {
+                self.evict(max_bytes / 10 * 9).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the cached blob for `digest`, calling `fetch` and caching the result on a miss.
+    pub async fn get_or_fetch<F, Fut>(&self, digest: &str, fetch: F) -> anyhow::Result<Vec<u8>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<Vec<u8>>>,
+    {
+        if let Some(data) = self.get(digest).await? {
+            return Ok(data);
+        }
+        let data = fetch().await?;
+        self.put(digest, &data).await?;
+        Ok(data)
+    }
+
+    /// Add `bytes` to the tracked size, returning the new size if it has been counted.
+    fn added(&self, bytes: u64) -> Option<u64> {
+        let mut size = self.size.lock().unwrap();
+        let size = size.as_mut()?;
+        *size += bytes;
+        Some(*size)
+    }
+
+    /// Count the bytes in the cache, starting the tracked size from there.
+    async fn count(&self) -> anyhow::Result<u64> {
+        let size = self.entries().await?.iter().map(|(_, size, _)| size).sum();
+        *self.size.lock().unwrap() = Some(size);
+        Ok(size)
+    }
+
+    /// Every cached blob, with its modification time and size. Blobs still being written
+    /// are left out, as are those removed while listing.
+    async fn entries(&self) -> anyhow::Result<Vec<(SystemTime, u64, PathBuf)>> {
+        let mut entries = vec![];
+        let mut algorithms = match tokio::fs::read_dir(&self.dir).await {
+            Ok(algorithms) => algorithms,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e).context("Listing blob cache"),
+        };
+        while let Some(algorithm) = algorithms.next_entry().await? {
+            if !algorithm.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut blobs = match tokio::fs::read_dir(algorithm.path()).await {
+                Ok(blobs) => blobs,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).context("Listing blob cache"),
+            };
+            while let Some(blob) = blobs.next_entry().await? {
+                if blob.file_name().to_string_lossy().contains(TMP_MARKER) {
+                    continue;
+                }
+                let metadata = match blob.metadata().await {
+                    Ok(metadata) => metadata,
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e).context("Listing blob cache"),
+                };
+                entries.push((metadata.modified()?, metadata.len(), blob.path()));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Remove the oldest blobs until the cache holds at most `target_bytes`. Skipped if
+    /// another insert is already evicting.
+    async fn evict(&self, target_bytes: u64) -> anyhow::Result<()> {
+        let Ok(_evicting) = self.evicting.try_lock() else {
+            return Ok(());
+        };
+        let mut entries = self.entries().await?;
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort();
+        for (_, size, path) in entries {
+            if total <= target_bytes {
+                break;
+            }
+            debug!("Evicting {} from blob cache", path.display());
+            match tokio::fs::remove_file(&path).await {
+                // Already evicted by another process sharing the cache.
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                result => result.with_context(|| format!("Evicting {}", path.display()))?,
+            }
+            total -= size;
+        }
+        *self.size.lock().unwrap() = Some(total);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        format!("sha256:{:x}", Sha256::digest(data))
+    }
+
+    #[tokio::test]
+    async fn round_trips_and_verifies_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path(), None);
+        let digest = sha256(b"config");
+
+        assert!(cache.put(&digest, b"other").await.is_err());
+        assert_eq!(cache.get(&digest).await.unwrap(), None);
+        cache.put(&digest, b"config").await.unwrap();
+        assert_eq!(cache.get(&digest).await.unwrap().unwrap(), b"config");
+
+        // A blob corrupted on disk is dropped and fetched again.
+        std::fs::write(cache.path(&digest).unwrap(), b"corrupt").unwrap();
+        let data = cache
+            .get_or_fetch(&digest, || async { Ok(b"config".to_vec()) })
+            .await
+            .unwrap();
+        assert_eq!(data, b"config");
+        assert_eq!(cache.get(&digest).await.unwrap().unwrap(), b"config");
+    }
+
+    #[tokio::test]
+    async fn evicts_oldest_blobs_and_skips_partial_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path(), Some(2500));
+        let partial = dir
+            .path()
+            .join("sha256")
+            .join(format!("abc{TMP_MARKER}1-2"));
+        let blobs = (0..4u8).map(|i| vec![i; 1000]).collect::<Vec<_>>();
+        for blob in &blobs {
+            cache.put(&sha256(blob), blob).await.unwrap();
+            std::fs::write(&partial, [0; 1000]).unwrap();
+            // Modification times must differ for the oldest to be evicted first.
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let cached = |blob: &Vec<u8>| cache.path(&sha256(blob)).unwrap().exists();
+        assert!(!cached(&blobs[0]));
+        assert!(!cached(&blobs[1]));
+        assert!(cached(&blobs[2]));
+        assert!(cached(&blobs[3]));
+        assert!(partial.exists());
+        assert_eq!(*cache.size.lock().unwrap(), Some(2000));
+    }
+}
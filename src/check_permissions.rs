@@ -0,0 +1,260 @@
+use anyhow::bail;
+use aws_config::SdkConfig;
+use aws_sdk_ecr::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_ecr::types::ImageIdentifier;
+use std::error::Error;
+use std::fmt::Debug;
+use tracing::{error, info, instrument, warn};
+
+const ACCESS_DENIED_CODES: &[&str] = &["AccessDeniedException", "AccessDenied"];
+
+/// Used for `BatchGetImage` when the probe repository has no images: a missing image is
+/// reported as a per-image failure, not an error, so only a denial fails the call.
+const PLACEHOLDER_DIGEST: &str =
+    "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+
+enum Outcome {
+    Allowed,
+    Denied,
+    /// The call failed for some other reason, so the permission is unknown.
+    Unknown(String),
+}
+
+fn outcome<T, E, R>(result: &Result<T, SdkError<E, R>>) -> Outcome
+where
+    E: ProvideErrorMetadata + Error + 'static,
+    R: Debug,
+{
+    outcome_allowing(result, &[])
+}
+
+/// Like [`outcome`], but errors with one of `allowed_codes` also show the call was
+/// authorized, such as a probe for a blob that doesn't exist.
+fn outcome_allowing<T, E, R>(result: &Result<T, SdkError<E, R>>, allowed_codes: &[&str]) -> Outcome
+where
+    E: ProvideErrorMetadata + Error + 'static,
+    R: Debug,
+{
+    match result {
+        Ok(_) => Outcome::Allowed,
+        Err(e)
+            if e.code()
+                .is_some_and(|code| ACCESS_DENIED_CODES.contains(&code)) =>
+        {
+            Outcome::Denied
+        }
+        Err(e) if e.code().is_some_and(|code| allowed_codes.contains(&code)) => Outcome::Allowed,
+        Err(e) => Outcome::Unknown(DisplayErrorContext(e).to_string()),
+    }
+}
+
+/// The registry and optional features of the dump to check permissions for.
+#[derive(Debug, Default)]
+pub struct Features {
+    pub registry_id: Option<String>,
+    pub fetch_config: bool,
+    pub scan_findings: bool,
+    pub repo_tags: bool,
+}
+
+impl Features {
+    /// The actions of the enabled features that need a repository to probe.
+    fn repository_actions(&self) -> Vec<&'static str> {
+        [
+            (self.fetch_config, GET_DOWNLOAD_URL),
+            (self.scan_findings, DESCRIBE_SCAN_FINDINGS),
+            (self.repo_tags, LIST_TAGS),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, action)| enabled.then_some(action))
+        .collect()
+    }
+}
+
+const GET_DOWNLOAD_URL: &str = "ecr:GetDownloadUrlForLayer (--fetch-config)";
+const DESCRIBE_SCAN_FINDINGS: &str = "ecr:DescribeImageScanFindings (--include-scan-findings)";
+const LIST_TAGS: &str = "ecr:ListTagsForResource (--repo-tags)";
+
+struct Report {
+    missing_required: Vec<&'static str>,
+    unverified_required: Vec<&'static str>,
+}
+
+impl Report {
+    fn record(&mut self, action: &'static str, required: bool, outcome: Outcome) {
+        match outcome {
+            Outcome::Allowed => info!("{action}: allowed"),
+            Outcome::Denied if required => {
+                error!("{action}: denied");
+                self.missing_required.push(action);
+            }
+            Outcome::Denied => warn!("{action}: denied (only needed for optional features)"),
+            Outcome::Unknown(reason) => {
+                warn!("{action}: could not be checked: {reason}");
+                if required {
+                    self.unverified_required.push(action);
+                }
+            }
+        }
+    }
+}
+
+/// Make a minimal call for each IAM action a dump needs, reporting which are allowed.
+/// Calls are made against `repository`, or the first repository listed if not given,
+/// in the registry of `features.registry_id`. The actions of enabled optional features
+/// are required too. Fails if any required action is denied or could not be checked.
+#[instrument(skip_all)]
+pub async fn check_permissions(
+    shared_config: &SdkConfig,
+    repository: Option<String>,
+    features: &Features,
+) -> anyhow::Result<()> {
+    let client = aws_sdk_ecr::Client::new(shared_config);
+    let registry_id = features.registry_id.clone();
+    let mut report = Report {
+        missing_required: vec![],
+        unverified_required: vec![],
+    };
+
+    let repositories = client
+        .describe_repositories()
+        .set_registry_id(registry_id.clone())
+        .set_repository_names(repository.clone().map(|repository| vec![repository]))
+        .set_max_results(repository.is_none().then_some(1))
+        .send()
+        .await;
+    let listed = repositories
+        .as_ref()
+        .ok()
+        .and_then(|output| output.repositories().first());
+    let repository_arn = listed
+        .and_then(|repository| repository.repository_arn())
+        .map(str::to_string);
+    let listed = listed
+        .and_then(|repository| repository.repository_name())
+        .map(str::to_string);
+    report.record("ecr:DescribeRepositories", true, outcome(&repositories));
+
+    match repository.or(listed) {
+        Some(repository) => {
+            info!("Probing repository {repository}");
+            let images = client
+                .describe_images()
+                .set_registry_id(registry_id.clone())
+                .repository_name(&repository)
+                .max_results(1)
+                .send()
+                .await;
+            let digest = images
+                .as_ref()
+                .ok()
+                .and_then(|output| output.image_details().first())
+                .and_then(|image| image.image_digest())
+                .unwrap_or(PLACEHOLDER_DIGEST)
+                .to_string();
+            report.record("ecr:DescribeImages", true, outcome(&images));
+
+            let batch = client
+                .batch_get_image()
+                .set_registry_id(registry_id.clone())
+                .repository_name(&repository)
+                .image_ids(ImageIdentifier::builder().image_digest(&digest).build())
+                .send()
+                .await;
+            report.record("ecr:BatchGetImage", true, outcome(&batch));
+
+            if features.fetch_config {
+                let download = client
+                    .get_download_url_for_layer()
+                    .set_registry_id(registry_id.clone())
+                    .repository_name(&repository)
+                    .layer_digest(PLACEHOLDER_DIGEST)
+                    .send()
+                    .await;
+                report.record(
+                    GET_DOWNLOAD_URL,
+                    true,
+                    outcome_allowing(&download, &["LayersNotFoundException"]),
+                );
+            }
+            if features.scan_findings {
+                let findings = client
+                    .describe_image_scan_findings()
+                    .set_registry_id(registry_id.clone())
+                    .repository_name(&repository)
+                    .image_id(ImageIdentifier::builder().image_digest(&digest).build())
+                    .max_results(1)
+                    .send()
+                    .await;
+                report.record(
+                    DESCRIBE_SCAN_FINDINGS,
+                    true,
+                    outcome_allowing(
+                        &findings,
+                        &["ScanNotFoundException", "ImageNotFoundException"],
+                    ),
+                );
+            }
+            if features.repo_tags {
+                match &repository_arn {
+                    Some(arn) => {
+                        let tags = client
+                            .list_tags_for_resource()
+                            .resource_arn(arn)
+                            .send()
+                            .await;
+                        report.record(LIST_TAGS, true, outcome(&tags));
+                    }
+                    None => report.record(
+                        LIST_TAGS,
+                        true,
+                        Outcome::Unknown(format!("no ARN listed for {repository}")),
+                    ),
+                }
+            }
+        }
+        None => {
+            let actions = ["ecr:DescribeImages", "ecr:BatchGetImage"]
+                .into_iter()
+                .chain(features.repository_actions())
+                .collect::<Vec<_>>();
+            warn!(
+                "No repository to probe: {} were not checked",
+                actions.join(", ")
+            );
+            report.unverified_required.extend(actions);
+        }
+    }
+
+    let cloudwatch = aws_sdk_cloudwatch::Client::new(shared_config);
+    let now = std::time::SystemTime::now();
+    let metrics = cloudwatch
+        .get_metric_statistics()
+        .namespace("AWS/ECR")
+        .metric_name("RepositoryPullCount")
+        .statistics(aws_sdk_cloudwatch::types::Statistic::Sum)
+        .period(3600)
+        .start_time((now - std::time::Duration::from_secs(3600)).into())
+        .end_time(now.into())
+        .send()
+        .await;
+    report.record(
+        "cloudwatch:GetMetricStatistics (--pull-metrics)",
+        false,
+        outcome(&metrics),
+    );
+
+    if !report.missing_required.is_empty() {
+        bail!(
+            "Missing required permissions: {}",
+            report.missing_required.join(", ")
+        );
+    }
+    if !report.unverified_required.is_empty() {
+        bail!(
+            "Could not verify required permissions: {}",
+            report.unverified_required.join(", ")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use anyhow::Context;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// Records each repository once its records have been flushed to the outputs, as a
+/// `<output offset>\t<repository>` line, so an interrupted dump can be resumed.
+///
+/// Within a repository, batches of images can also be recorded as they are flushed, as
+/// `<output offset>\t<repository>\t<digest>,<digest>,...` lines, so resuming a large
+/// repository only skips the images already written.
+pub struct Checkpoint {
+    file: tokio::fs::File,
+    base_offset: u64,
+    resumed_images: HashMap<String, HashSet<String>>,
+}
+
+/// What a previous run completed.
+#[derive(Debug, Default)]
+pub struct Resumed {
+    pub repositories: HashSet<String>,
+    /// Length of the outputs after the last completed repository. Anything after this
+    /// is from a repository that was interrupted and will be fetched again.
+    pub offset: u64,
+    /// Digests already written from repositories that were interrupted partway, by key.
+    pub images: HashMap<String, HashSet<String>>,
+}
+
+/// The checkpoint key of a repository, qualified by region when dumping several.
+pub fn key(region: Option<&str>, repository: &str) -> String {
+    match region {
+        Some(region) => format!("{region}/{repository}"),
+        None => repository.to_string(),
+    }
+}
+
+/// Read a checkpoint file. A missing file means nothing was completed.
+pub async fn load(path: &Path) -> anyhow::Result<Resumed> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Resumed::default()),
+        Err(e) => return Err(e).with_context(|| format!("Reading checkpoint {}", path.display())),
+    };
+    let mut resumed = Resumed::default();
+    // A line cut short by the crash has no newline and is ignored.
+    for line in contents.split_inclusive('\n') {
+        let Some(line) = line.strip_suffix('\n') else {
+            continue;
+        };
+        let mut fields = line.split('\t');
+        let (Some(offset), Some(repository)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Ok(offset) = offset.parse() else {
+            continue;
+        };
+        resumed.offset = offset;
+        match fields.next() {
+            Some(digests) => resumed
+                .images
+                .entry(repository.to_string())
+                .or_default()
+                .extend(digests.split(',').map(str::to_string)),
+            None => {
+                resumed.images.remove(repository);
+                resumed.repositories.insert(repository.to_string());
+            }
+        }
+    }
+    Ok(resumed)
+}
+
+impl Checkpoint {
+    /// Open the checkpoint, appending after `resumed` or starting afresh if `None`.
+    pub async fn open(path: &Path, resumed: Option<&Resumed>) -> anyhow::Result<Self> {
+        let mut options = tokio::fs::OpenOptions::new();
+        options.create(true);
+        match resumed {
+            Some(_) => options.append(true),
+            None => options.write(true).truncate(true),
+        };
+        let file = options
+            .open(path)
+            .await
+            .with_context(|| format!("Opening checkpoint {}", path.display()))?;
+        Ok(Self {
+            file,
+            base_offset: resumed.map_or(0, |resumed| resumed.offset),
+            resumed_images: resumed
+                .map(|resumed| resumed.images.clone())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The digests a previous run already wrote from the repository `key`, if it was
+    /// interrupted partway through.
+    pub fn take_written_images(&mut self, key: &str) -> HashSet<String> {
+        self.resumed_images.remove(key).unwrap_or_default()
+    }
+
+    /// Record `key` as complete, `written` bytes into this run's output.
+    pub async fn record(&mut self, written: u64, key: &str) -> anyhow::Result<()> {
+        let line = format!("{}\t{key}\n", self.base_offset + written);
+        self.file
+            .write_all(line.as_bytes())
+            .await
+            .context("Writing checkpoint")?;
+        self.file.sync_data().await.context("Writing checkpoint")
+    }
+
+    /// Record `digests` of the repository `key` as written, `written` bytes into this
+    /// run's output.
+    pub async fn record_images(
+        &mut self,
+        written: u64,
+        key: &str,
+        digests: &[String],
+    ) -> anyhow::Result<()> {
+        let line = format!(
+            "{}\t{key}\t{}\n",
+            self.base_offset + written,
+            digests.join(",")
+        );
+        self.file
+            .write_all(line.as_bytes())
+            .await
+            .context("Writing checkpoint")?;
+        self.file.sync_data().await.context("Writing checkpoint")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resumes_images_of_interrupted_repositories() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        let mut checkpoint = Checkpoint::open(&path, None).await.unwrap();
+        checkpoint
+            .record_images(10, "done", &["sha256:a".to_string()])
+            .await
+            .unwrap();
+        checkpoint.record(20, "done").await.unwrap();
+        let digests = ["sha256:b".to_string(), "sha256:c".to_string()];
+        checkpoint
+            .record_images(30, "partial", &digests)
+            .await
+            .unwrap();
+        checkpoint
+            .record_images(40, "partial", &["sha256:d".to_string()])
+            .await
+            .unwrap();
+        drop(checkpoint);
+        // A batch cut short by a crash.
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str("50\tpartial\tsha256:e,sha");
+        std::fs::write(&path, contents).unwrap();
+
+        let resumed = load(&path).await.unwrap();
+        assert_eq!(resumed.offset, 40);
+        assert_eq!(resumed.repositories, HashSet::from(["done".to_string()]));
+        assert_eq!(resumed.images.len(), 1);
+
+        let mut checkpoint = Checkpoint::open(&path, Some(&resumed)).await.unwrap();
+        let mut written = checkpoint
+            .take_written_images("partial")
+            .into_iter()
+            .collect::<Vec<_>>();
+        written.sort();
+        assert_eq!(written, ["sha256:b", "sha256:c", "sha256:d"]);
+        assert!(checkpoint.take_written_images("partial").is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Upper bound on repositories processed at once when sizing automatically.
+const AUTO_MAX_REPOS: usize = 8;
+/// Roughly how many ECR requests `auto` aims to keep in flight across all repositories.
+const AUTO_TARGET_IN_FLIGHT: usize = 50;
+
+/// A concurrency setting: either a fixed number, or `auto`.
+///
+/// `auto` processes `min(cpus, 8)` repositories at once, and lets each repository
+/// resolve `50 / repositories` batches at once (at least 2), so roughly 50 requests
+/// are in flight overall. A fixed number is used for both.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Concurrency {
+    Auto,
+    Fixed(usize),
+}
+
+impl Concurrency {
+    pub fn repos(&self) -> usize {
+        match self {
+            Concurrency::Auto => std::thread::available_parallelism()
+                .map_or(1, |cpus| cpus.get())
+                .min(AUTO_MAX_REPOS),
+            Concurrency::Fixed(n) => *n,
+        }
+    }
+
+    /// Requests per repository when `repos` repositories are processed at once.
+    pub fn images(&self, repos: usize) -> usize {
+        match self {
+            Concurrency::Auto => (AUTO_TARGET_IN_FLIGHT / repos.max(1)).max(2),
+            Concurrency::Fixed(n) => *n,
+        }
+    }
+}
+
+impl FromStr for Concurrency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Concurrency::Auto);
+        }
+        match s.parse::<usize>() {
+            Ok(0) => Err("concurrency must be at least 1".to_string()),
+            Ok(n) => Ok(Concurrency::Fixed(n)),
+            Err(_) => Err(format!("expected a number or `auto`, got {s:?}")),
+        }
+    }
+}
+
+impl Display for Concurrency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Concurrency::Auto => f.write_str("auto"),
+            Concurrency::Fixed(n) => write!(f, "{n}"),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::{bail, Context};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use toml::Value;
+
+/// Arguments that can't be set from a config file.
+const RESERVED: &[&str] = &["config", "help", "version"];
+
+/// The process arguments, with the settings from the `--config` TOML file added for
+/// every option not given on the command line, or conflicting with one that is.
+///
+/// Keys are option names, in kebab- or snake_case, with `outputs` for the positional
+/// outputs. Flags take booleans, `verbose` a count, and repeated options take arrays.
+/// Any unknown key is an error. The file is ignored when running a subcommand.
+pub fn args_with_config(command: Command) -> anyhow::Result<Vec<OsString>> {
+    with_config(command, std::env::args_os().collect())
+}
+
+fn with_config(command: Command, cli: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    // Errors such as missing outputs are left to the real parse, after the config is added.
+    let Ok(matches) = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&cli)
+    else {
+        return Ok(cli);
+    };
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(cli);
+    };
+    if matches.subcommand().is_some() {
+        return Ok(cli);
+    }
+    let table: toml::Table = std::fs::read_to_string(path)
+        .with_context(|| format!("Reading config file {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Parsing config file {}", path.display()))?;
+
+    let mut options = vec![];
+    let mut positionals = vec![];
+    let mut unknown = vec![];
+    for (key, value) in table {
+        let id = key.replace('-', "_");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str() && !RESERVED.contains(&id.as_str()))
+        else {
+            unknown.push(key);
+            continue;
+        };
+        if on_command_line(&matches, arg)
+            || conflicting(&command, arg).any(|other| on_command_line(&matches, other))
+        {
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(s) => s,
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => value.to_string(),
+                _ => bail!(
+                    "{}: `{key}` must be a string, number, boolean or an array of them",
+                    path.display()
+                ),
+            };
+            match arg.get_long() {
+                Some(long) if matches!(arg.get_action(), ArgAction::SetTrue) => {
+                    match value.as_str() {
+                        "true" => options.push(format!("--{long}")),
+                        "false" => {}
+                        _ => bail!("{}: `{key}` must be true or false", path.display()),
+                    }
+                }
+                Some(long) if matches!(arg.get_action(), ArgAction::Count) => {
+                    let count: usize = value
+                        .parse()
+                        .with_context(|| format!("{}: `{key}` must be a count", path.display()))?;
+                    options.extend(std::iter::repeat_n(format!("--{long}"), count));
+                }
+                Some(long) => options.push(format!("--{long}={value}")),
+                None => positionals.push(value),
+            }
+        }
+    }
+    if !unknown.is_empty() {
+        bail!(
+            "{}: unknown settings {}",
+            path.display(),
+            unknown
+                .iter()
+                .map(|key| format!("`{key}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let mut args = cli;
+    args.splice(1..1, options.into_iter().map(OsString::from));
+    if !positionals.is_empty() {
+        args.push("--".into());
+        args.extend(positionals.into_iter().map(OsString::from));
+    }
+    Ok(args)
+}
+
+fn on_command_line(matches: &ArgMatches, arg: &Arg) -> bool {
+    matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+}
+
+/// The arguments that `arg` conflicts with, whichever side declares the conflict.
+fn conflicting<'a>(command: &'a Command, arg: &'a Arg) -> impl Iterator<Item = &'a Arg> {
+    command.get_arg_conflicts_with(arg).into_iter().chain(
+        command
+            .get_arguments()
+            .filter(move |other| command.get_arg_conflicts_with(other).contains(&arg)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::value_parser;
+
+    fn command() -> Command {
+        Command::new("ecr-dump")
+            .arg(
+                Arg::new("config")
+                    .long("config")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(Arg::new("region").long("region"))
+            .arg(Arg::new("profile").long("profile").conflicts_with("region"))
+            .arg(Arg::new("tee").long("tee").action(ArgAction::SetTrue))
+            .arg(
+                Arg::new("include")
+                    .long("include")
+                    .action(ArgAction::Append),
+            )
+            .arg(Arg::new("outputs").num_args(1..))
+    }
+
+    /// The arguments `cli` expands to with `config` as the config file.
+    fn expand(config: &str, cli: &[&str]) -> anyhow::Result<Vec<String>> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, config).unwrap();
+        let path = path.to_str().unwrap();
+        let cli = ["ecr-dump", "--config", path]
+            .iter()
+            .chain(cli)
+            .map(OsString::from)
+            .collect();
+        let args = with_config(command(), cli)?;
+        Ok(args[1..]
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .filter(|arg| arg != "--config" && arg != path)
+            .collect())
+    }
+
+    #[test]
+    fn adds_options_not_on_the_command_line() {
+        assert_eq!(
+            expand("region = 'eu-west-1'", &["out.jsonl"]).unwrap(),
+            ["--region=eu-west-1", "out.jsonl"]
+        );
+    }
+
+    #[test]
+    fn command_line_wins() {
+        assert_eq!(
+            expand("region = 'eu-west-1'", &["--region", "us-east-1"]).unwrap(),
+            ["--region", "us-east-1"]
+        );
+        // A setting that conflicts with a command-line flag is dropped too.
+        assert_eq!(
+            expand("profile = 'dev'", &["--region", "us-east-1"]).unwrap(),
+            ["--region", "us-east-1"]
+        );
+    }
+
+    #[test]
+    fn booleans_set_flags() {
+        assert_eq!(expand("tee = true", &[]).unwrap(), ["--tee"]);
+        assert!(expand("tee = false", &[]).unwrap().is_empty());
+        let error = expand("tee = 'yes'", &[]).unwrap_err().to_string();
+        assert!(error.ends_with("`tee` must be true or false"), "{error}");
+    }
+
+    #[test]
+    fn arrays_repeat_the_option() {
+        assert_eq!(
+            expand("include = ['team-a/*', 'team-b/*']", &[]).unwrap(),
+            ["--include=team-a/*", "--include=team-b/*"]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_reserved_keys() {
+        let error = expand("regoin = 'eu-west-1'\nhelp = true", &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.ends_with("unknown settings `help`, `regoin`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn positionals_follow_a_separator() {
+        assert_eq!(
+            expand("outputs = ['--weird.jsonl', 'b.jsonl']", &["--tee"]).unwrap(),
+            ["--tee", "--", "--weird.jsonl", "b.jsonl"]
+        );
+        // Outputs given on the command line replace the configured ones.
+        assert_eq!(
+            expand("outputs = ['a.jsonl']", &["c.jsonl"]).unwrap(),
+            ["c.jsonl"]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use ecr_dump::images::ImageWithManifests;
+
+pub const HEADER: [&str; 8] = [
+    "repository",
+    "digest",
+    "manifest_type",
+    "tags",
+    "pushed_at",
+    "last_pulled",
+    "platform_digest",
+    "layer_count",
+];
+
+/// Flatten an image into CSV rows, one per platform manifest: a single-platform image
+/// gives one row, a manifest list one row per child with its digest in
+/// `platform_digest`. An image with no manifests still gets a row, with the last two
+/// columns empty. Tags are joined with commas into one field.
+pub fn rows(image: &ImageWithManifests) -> anyhow::Result<Vec<String>> {
+    let tags = image.image.image_tags.join(",");
+    let pushed_at = image.image.image_pushed_at.to_rfc3339();
+    let last_pulled = image
+        .image
+        .last_recorded_pull_time
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default();
+    let common = [
+        image.image.repository_name.as_str(),
+        image.image.manifest_digest.as_str(),
+        &image.image.manifest_type.to_string(),
+        &tags,
+        &pushed_at,
+        &last_pulled,
+    ]
+    .map(str::to_string);
+
+    let platforms: Vec<[String; 2]> = if image.manifests.is_empty() {
+        vec![Default::default()]
+    } else {
+        image
+            .manifests
+            .iter()
+            .map(|manifest| {
+                let digest = manifest
+                    .descriptor
+                    .as_ref()
+                    .map(|d| d.digest().to_string())
+                    .unwrap_or_default();
+                [digest, manifest.content.layers().len().to_string()]
+            })
+            .collect()
+    };
+    platforms
+        .iter()
+        .map(|platform| format_row(common.iter().chain(platform)))
+        .collect()
+}
+
+/// A single CSV line, without the line terminator, quoting fields as needed.
+pub fn format_row<I, S>(fields: I) -> anyhow::Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<[u8]>,
+{
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    writer.write_record(fields)?;
+    let mut line = String::from_utf8(writer.into_inner()?)?;
+    line.pop();
+    Ok(line)
+}
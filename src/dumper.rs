@@ -0,0 +1,308 @@
+use crate::ecr_api::is_repository_not_found;
+use crate::images::{
+    merge_standalone_children, ApiCallCounts, FetchOptions, ImageFailure, ImageFetcher,
+    ImageWithManifests,
+};
+use crate::progress;
+use crate::repos::{RepositoryLister, RepositoryName};
+use crate::signing::SigningCoverage;
+use anyhow::Context;
+use aws_sdk_ecr::Client;
+use futures_util::stream::{self as stream, StreamExt, TryStreamExt};
+use globset::GlobSet;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+use tracing::{debug, info, instrument, warn};
+use tracing_indicatif::span_ext::IndicatifSpanExt;
+
+/// The resolved images of a single repository.
+#[derive(Debug)]
+pub struct FetchedRepository {
+    pub repository_name: RepositoryName,
+    pub images: Vec<ImageWithManifests>,
+    pub api_calls: ApiCallCounts,
+    pub signing: Option<SigningCoverage>,
+    /// Listing the repository failed partway and only some of its images were fetched.
+    pub partial: bool,
+    /// Manifests `BatchGetImage` couldn't return.
+    pub failures: Vec<ImageFailure>,
+}
+
+/// Fetches and resolves the images in ECR repositories using a caller-supplied client.
+pub struct Dumper {
+    client: Client,
+    concurrency: usize,
+    pub fetch_options: FetchOptions,
+    /// Stamp the client's region onto every resolved image.
+    pub record_region: bool,
+    /// Skip repositories with fewer images than this, before resolving them.
+    pub min_repo_images: usize,
+    /// Summarise cosign signature and attestation coverage for each repository.
+    pub signing_coverage: bool,
+    /// Fold images that are also manifest list children into the list's record.
+    pub dedupe_records: bool,
+    /// Warn before resolving a repository with more images than this.
+    pub warn_large_repo: Option<usize>,
+    /// Images requested per `DescribeImages` page, at most 1000.
+    pub page_size: i32,
+    /// Manifests requested per `BatchGetImage` call, at most 100.
+    pub batch_size: usize,
+    /// Fail if a repository was deleted after being listed, instead of skipping it with a
+    /// warning.
+    pub fail_on_repo_errors: bool,
+    repository_tags: Mutex<HashMap<String, BTreeMap<String, String>>>,
+}
+
+impl Dumper {
+    pub fn new(client: Client) -> Self {
+        Self::new_with_concurrency(client, 10)
+    }
+
+    pub fn new_with_concurrency(client: Client, concurrency: usize) -> Self {
+        Self {
+            client,
+            concurrency,
+            fetch_options: FetchOptions::default(),
+            record_region: false,
+            min_repo_images: 0,
+            signing_coverage: false,
+            dedupe_records: false,
+            warn_large_repo: None,
+            page_size: 1000,
+            batch_size: 100,
+            fail_on_repo_errors: false,
+            repository_tags: Mutex::default(),
+        }
+    }
+
+    /// Build a client from the standard AWS environment (`AWS_PROFILE`, `AWS_REGION`, ...),
+    /// with the SDK's retries disabled in favour of the [`RetryPolicy`](crate::RetryPolicy).
+    #[cfg(feature = "env-config")]
+    pub async fn from_env() -> Self {
+        let shared_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .retry_config(aws_config::retry::RetryConfig::disabled())
+            .load()
+            .await;
+        Self::new(Client::new(&shared_config))
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.client.config().region().map(|r| r.as_ref())
+    }
+
+    /// List the repositories matching `include` and not matching `exclude`, sorted by
+    /// name, from the registry in `fetch_options.registry_id`.
+    pub async fn list_repositories(
+        &self,
+        include: Option<GlobSet>,
+        exclude: Option<GlobSet>,
+    ) -> anyhow::Result<Vec<RepositoryName>> {
+        let mut lister = RepositoryLister::new(self.client.clone(), include, exclude);
+        lister
+            .registry_id
+            .clone_from(&self.fetch_options.registry_id);
+        lister.retry = self.fetch_options.retry.clone();
+        lister
+            .rate_limiter
+            .clone_from(&self.fetch_options.rate_limiter);
+        lister.metrics = self.fetch_options.metrics.clone();
+        lister.list().await
+    }
+
+    /// The resource tags on the repository with this ARN, fetched once and then cached.
+    pub async fn repository_tags(
+        &self,
+        repository_arn: &str,
+    ) -> anyhow::Result<BTreeMap<String, String>> {
+        if let Some(tags) = self.repository_tags.lock().unwrap().get(repository_arn) {
+            return Ok(tags.clone());
+        }
+        let output = self
+            .fetch_options
+            .retry
+            .retry("ListTagsForResource", || async {
+                if let Some(rate_limiter) = &self.fetch_options.rate_limiter {
+                    rate_limiter.acquire().await;
+                }
+                self.fetch_options.metrics.add_api_call();
+                self.client
+                    .list_tags_for_resource()
+                    .resource_arn(repository_arn)
+                    .send()
+                    .await
+            })
+            .await
+            .with_context(|| format!("Listing tags of {repository_arn}"))?;
+        let tags: BTreeMap<String, String> = output
+            .tags
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tag| (tag.key, tag.value))
+            .collect();
+        self.repository_tags
+            .lock()
+            .unwrap()
+            .insert(repository_arn.to_string(), tags.clone());
+        Ok(tags)
+    }
+
+    fn image_fetcher(&self, repo_name: RepositoryName) -> ImageFetcher {
+        let mut image_fetcher = ImageFetcher::new_with_config(
+            self.client.clone(),
+            repo_name,
+            self.page_size,
+            self.batch_size,
+            self.concurrency,
+        );
+        image_fetcher.options = self.fetch_options.clone();
+        image_fetcher
+    }
+
+    /// Count the images that would be resolved across `repo_names`, without resolving them.
+    pub async fn count_images(&self, repo_names: &[RepositoryName]) -> anyhow::Result<usize> {
+        let counts = self.count_images_by_repository(repo_names).await?;
+        Ok(counts.iter().map(|(_, count)| count).sum())
+    }
+
+    /// Like [`Dumper::count_images`], but per repository, in the order of `repo_names`.
+    #[instrument(skip_all)]
+    pub async fn count_images_by_repository(
+        &self,
+        repo_names: &[RepositoryName],
+    ) -> anyhow::Result<Vec<(RepositoryName, usize)>> {
+        let span = progress::set_span_progress("counting", repo_names.len());
+        stream::iter(repo_names.iter().map(|repo_name| {
+            let image_fetcher = self.image_fetcher(repo_name.clone());
+            async move {
+                let count = image_fetcher
+                    .count_images()
+                    .await
+                    .with_context(|| format!("Counting images in {repo_name}"))?;
+                anyhow::Ok((repo_name.clone(), count))
+            }
+        }))
+        .buffered(self.concurrency)
+        .inspect(|_| span.pb_inc(1))
+        .try_collect()
+        .await
+    }
+
+    /// Fetch each repository in turn, passing every resolved image to `f`, with up to
+    /// `concurrency` calls to `f` in flight. The next repository is only fetched once
+    /// `f` has accepted the previous one's images, so a slow consumer slows the dump.
+    ///
+    /// The first error, from fetching or from `f`, stops the dump: no further
+    /// repositories are fetched, in-flight calls to `f` are dropped, and the error is
+    /// returned.
+    pub async fn dump_for_each_concurrent<F, Fut>(
+        &self,
+        repo_names: impl IntoIterator<Item = RepositoryName>,
+        concurrency: usize,
+        f: F,
+    ) -> anyhow::Result<()>
+    where
+        F: Fn(ImageWithManifests) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        stream::iter(repo_names)
+            .then(|repo_name| self.fetch_repo(repo_name))
+            .map_ok(|repo| stream::iter(repo.into_iter().flat_map(|r| r.images).map(Ok)))
+            .try_flatten()
+            .try_for_each_concurrent(concurrency, f)
+            .await
+    }
+
+    /// Fetch and resolve every image in a repository. Returns `None` if the repository
+    /// was skipped for having fewer than `min_repo_images` images, or for having been
+    /// deleted unless `fail_on_repo_errors` is set.
+    #[instrument(skip(self))]
+    pub async fn fetch_repo(
+        &self,
+        repo_name: RepositoryName,
+    ) -> anyhow::Result<Option<FetchedRepository>> {
+        let result = self.fetch_and_resolve(repo_name.clone()).await;
+        match &result {
+            Ok(Some(_)) => self.fetch_options.metrics.add_repository(),
+            Ok(None) => {}
+            Err(_) => self.fetch_options.metrics.add_errors(1),
+        }
+        match result {
+            Err(e) if !self.fail_on_repo_errors && is_repository_not_found(&e) => {
+                warn!("Skipping {repo_name}: it was deleted during the dump");
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    async fn fetch_and_resolve(
+        &self,
+        repo_name: RepositoryName,
+    ) -> anyhow::Result<Option<FetchedRepository>> {
+        let image_fetcher = self.image_fetcher(repo_name.clone());
+        let images = image_fetcher.fetch_images().await?;
+        debug!("Found {} images:", images.len());
+        if images.len() < self.min_repo_images {
+            info!(
+                "Skipping {repo_name}: {} images is below the minimum of {}",
+                images.len(),
+                self.min_repo_images
+            );
+            return Ok(None);
+        }
+        if let Some(threshold) = self.warn_large_repo {
+            if images.len() > threshold {
+                warn!(
+                    "{repo_name} has {} images, more than the large repository threshold of {threshold}",
+                    images.len()
+                );
+            }
+        }
+        let mut resolved = image_fetcher
+            .resolve_images(&images)
+            .await
+            .with_context(|| format!("Resolving {repo_name}"))?;
+        if self.dedupe_records {
+            resolved = merge_standalone_children(resolved);
+        }
+        self.fetch_options.metrics.add_images(
+            resolved.len() as u64,
+            resolved
+                .iter()
+                .map(|image| image.stats.total_size as u64)
+                .sum(),
+        );
+        if self.record_region {
+            let region = self.region().map(str::to_string);
+            for image in &mut resolved {
+                image.region.clone_from(&region);
+            }
+        }
+        debug!("Resolved {} images with manifests", resolved.len());
+        let api_calls = image_fetcher.api_calls();
+        info!(
+            "{repo_name} made {} DescribeImages and {} BatchGetImage calls",
+            api_calls.describe_images, api_calls.batch_get_image
+        );
+        let signing = self
+            .signing_coverage
+            .then(|| SigningCoverage::from_images(&resolved));
+        Ok(Some(FetchedRepository {
+            repository_name: repo_name,
+            images: resolved,
+            api_calls,
+            signing,
+            partial: image_fetcher.is_partial(),
+            failures: image_fetcher.failures(),
+        }))
+    }
+}
@@ -0,0 +1,137 @@
+use aws_sdk_ecr::config::http::HttpResponse;
+use aws_sdk_ecr::error::SdkError;
+use aws_sdk_ecr::operation::batch_get_image::{
+    BatchGetImageError, BatchGetImageInput, BatchGetImageOutput,
+};
+use aws_sdk_ecr::operation::describe_image_scan_findings::{
+    DescribeImageScanFindingsError, DescribeImageScanFindingsInput, DescribeImageScanFindingsOutput,
+};
+use aws_sdk_ecr::operation::describe_images::{
+    DescribeImagesError, DescribeImagesInput, DescribeImagesOutput,
+};
+use aws_sdk_ecr::operation::describe_repositories::{
+    DescribeRepositoriesError, DescribeRepositoriesInput, DescribeRepositoriesOutput,
+};
+use aws_sdk_ecr::operation::get_download_url_for_layer::{
+    GetDownloadUrlForLayerError, GetDownloadUrlForLayerInput, GetDownloadUrlForLayerOutput,
+};
+use aws_sdk_ecr::Client;
+use std::future::Future;
+
+pub type EcrResult<T, E> = Result<T, SdkError<E, HttpResponse>>;
+
+/// Whether `error` came from listing or fetching the images of a repository that no
+/// longer exists.
+pub fn is_repository_not_found(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<SdkError<DescribeImagesError, HttpResponse>>()
+            .and_then(SdkError::as_service_error)
+            .is_some_and(DescribeImagesError::is_repository_not_found_exception)
+            || cause
+                .downcast_ref::<SdkError<BatchGetImageError, HttpResponse>>()
+                .and_then(SdkError::as_service_error)
+                .is_some_and(BatchGetImageError::is_repository_not_found_exception)
+    })
+}
+
+/// The ECR calls made by [`ImageFetcher`](crate::images::ImageFetcher) and
+/// [`RepositoryLister`](crate::repos::RepositoryLister). Implemented for the real
+/// [`Client`]; implement it over in-memory fixtures to exercise them without AWS.
+pub trait EcrApi: Clone + Send + Sync + 'static {
+    fn describe_repositories(
+        &self,
+        input: DescribeRepositoriesInput,
+    ) -> impl Future<Output = EcrResult<DescribeRepositoriesOutput, DescribeRepositoriesError>> + Send;
+
+    fn describe_images(
+        &self,
+        input: DescribeImagesInput,
+    ) -> impl Future<Output = EcrResult<DescribeImagesOutput, DescribeImagesError>> + Send;
+
+    fn batch_get_image(
+        &self,
+        input: BatchGetImageInput,
+    ) -> impl Future<Output = EcrResult<BatchGetImageOutput, BatchGetImageError>> + Send;
+
+    fn get_download_url_for_layer(
+        &self,
+        input: GetDownloadUrlForLayerInput,
+    ) -> impl Future<Output = EcrResult<GetDownloadUrlForLayerOutput, GetDownloadUrlForLayerError>> + Send;
+
+    fn describe_image_scan_findings(
+        &self,
+        input: DescribeImageScanFindingsInput,
+    ) -> impl Future<
+        Output = EcrResult<DescribeImageScanFindingsOutput, DescribeImageScanFindingsError>,
+    > + Send;
+}
+
+impl EcrApi for Client {
+    async fn describe_repositories(
+        &self,
+        input: DescribeRepositoriesInput,
+    ) -> EcrResult<DescribeRepositoriesOutput, DescribeRepositoriesError> {
+        self.describe_repositories()
+            .set_registry_id(input.registry_id)
+            .set_repository_names(input.repository_names)
+            .set_next_token(input.next_token)
+            .set_max_results(input.max_results)
+            .send()
+            .await
+    }
+
+    async fn describe_images(
+        &self,
+        input: DescribeImagesInput,
+    ) -> EcrResult<DescribeImagesOutput, DescribeImagesError> {
+        self.describe_images()
+            .set_registry_id(input.registry_id)
+            .set_repository_name(input.repository_name)
+            .set_image_ids(input.image_ids)
+            .set_next_token(input.next_token)
+            .set_max_results(input.max_results)
+            .set_filter(input.filter)
+            .send()
+            .await
+    }
+
+    async fn batch_get_image(
+        &self,
+        input: BatchGetImageInput,
+    ) -> EcrResult<BatchGetImageOutput, BatchGetImageError> {
+        self.batch_get_image()
+            .set_registry_id(input.registry_id)
+            .set_repository_name(input.repository_name)
+            .set_image_ids(input.image_ids)
+            .set_accepted_media_types(input.accepted_media_types)
+            .send()
+            .await
+    }
+
+    async fn get_download_url_for_layer(
+        &self,
+        input: GetDownloadUrlForLayerInput,
+    ) -> EcrResult<GetDownloadUrlForLayerOutput, GetDownloadUrlForLayerError> {
+        self.get_download_url_for_layer()
+            .set_registry_id(input.registry_id)
+            .set_repository_name(input.repository_name)
+            .set_layer_digest(input.layer_digest)
+            .send()
+            .await
+    }
+
+    async fn describe_image_scan_findings(
+        &self,
+        input: DescribeImageScanFindingsInput,
+    ) -> EcrResult<DescribeImageScanFindingsOutput, DescribeImageScanFindingsError> {
+        self.describe_image_scan_findings()
+            .set_registry_id(input.registry_id)
+            .set_repository_name(input.repository_name)
+            .set_image_id(input.image_id)
+            .set_next_token(input.next_token)
+            .set_max_results(input.max_results)
+            .send()
+            .await
+    }
+}
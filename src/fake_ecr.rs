@@ -0,0 +1,393 @@
+//! An in-memory [`EcrApi`] for tests, holding repositories of pushed manifests.
+
+use crate::ecr_api::{EcrApi, EcrResult};
+use aws_sdk_ecr::config::http::HttpResponse;
+use aws_sdk_ecr::error::{ErrorMetadata, SdkError};
+use aws_sdk_ecr::operation::batch_get_image::{
+    BatchGetImageError, BatchGetImageInput, BatchGetImageOutput,
+};
+use aws_sdk_ecr::operation::describe_image_scan_findings::{
+    DescribeImageScanFindingsError, DescribeImageScanFindingsInput, DescribeImageScanFindingsOutput,
+};
+use aws_sdk_ecr::operation::describe_images::{
+    DescribeImagesError, DescribeImagesInput, DescribeImagesOutput,
+};
+use aws_sdk_ecr::operation::describe_repositories::{
+    DescribeRepositoriesError, DescribeRepositoriesInput, DescribeRepositoriesOutput,
+};
+use aws_sdk_ecr::operation::get_download_url_for_layer::{
+    GetDownloadUrlForLayerError, GetDownloadUrlForLayerInput, GetDownloadUrlForLayerOutput,
+};
+use aws_sdk_ecr::primitives::DateTime;
+use aws_sdk_ecr::types::error::RepositoryNotFoundException;
+use aws_sdk_ecr::types::{
+    Image, ImageDetail, ImageFailure, ImageFailureCode, ImageIdentifier, Repository, TagStatus,
+};
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_runtime_api::http::StatusCode;
+use aws_smithy_types::body::SdkBody;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+pub const IMAGE_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+fn sha256(content: &str) -> String {
+    format!("sha256:{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// An OCI image manifest with one layer, made distinct by `seed`.
+pub fn image_manifest(seed: &str) -> String {
+    serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": IMAGE_MEDIA_TYPE,
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": sha256(&format!("config {seed}")),
+            "size": 100,
+        },
+        "layers": [{
+            "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+            "digest": sha256(&format!("layer {seed}")),
+            "size": 1000,
+        }],
+    })
+    .to_string()
+}
+
+/// An OCI index of the given `(digest, size)` image manifests, all `linux/amd64`.
+pub fn index_manifest(children: &[(String, usize)]) -> String {
+    let manifests = children
+        .iter()
+        .map(|(digest, size)| {
+            serde_json::json!({
+                "mediaType": IMAGE_MEDIA_TYPE,
+                "digest": digest,
+                "size": size,
+                "platform": {"os": "linux", "architecture": "amd64"},
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": INDEX_MEDIA_TYPE,
+        "manifests": manifests,
+    })
+    .to_string()
+}
+
+#[derive(Debug, Clone)]
+struct FakeImage {
+    digest: String,
+    media_type: String,
+    manifest: String,
+    tags: Vec<String>,
+    size: Option<i64>,
+    pushed_at: i64,
+}
+
+/// An error queued for the next call to an operation.
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    /// A `ThrottlingException`, with its `Retry-After` in seconds.
+    Throttle(Option<u64>),
+    /// A connection failure before any response arrived.
+    Dispatch,
+}
+
+impl Fault {
+    fn into_error<E>(self, error: fn(ErrorMetadata) -> E) -> SdkError<E, HttpResponse> {
+        match self {
+            Fault::Throttle(retry_after) => throttled(error, retry_after),
+            Fault::Dispatch => SdkError::dispatch_failure(ConnectorError::io(
+                std::io::Error::from(std::io::ErrorKind::ConnectionReset).into(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    repositories: BTreeMap<String, Vec<FakeImage>>,
+    /// Errors still to be returned by each operation.
+    faults: HashMap<&'static str, VecDeque<Fault>>,
+    calls: HashMap<&'static str, usize>,
+    batch_sizes: Vec<usize>,
+    blob_url: Option<String>,
+}
+
+impl State {
+    /// Count a call to `operation`, returning the fault queued for it, if any.
+    fn call(&mut self, operation: &'static str) -> Option<Fault> {
+        *self.calls.entry(operation).or_default() += 1;
+        self.faults.get_mut(operation)?.pop_front()
+    }
+
+    fn queue(&mut self, operation: &'static str, fault: Fault) {
+        self.faults.entry(operation).or_default().push_back(fault);
+    }
+}
+
+/// Cloning shares the same registry, so a test can keep a handle to inspect calls.
+#[derive(Debug, Clone, Default)]
+pub struct FakeEcr {
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeEcr {
+    pub fn add_repository(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.repositories.entry(name.to_string()).or_default();
+    }
+
+    /// Push `manifest` to `repository`, creating it if needed, and return its digest.
+    pub fn push(
+        &self,
+        repository: &str,
+        media_type: &str,
+        manifest: &str,
+        tags: &[&str],
+    ) -> String {
+        let digest = sha256(manifest);
+        let mut state = self.state.lock().unwrap();
+        let images = state
+            .repositories
+            .entry(repository.to_string())
+            .or_default();
+        images.push(FakeImage {
+            digest: digest.clone(),
+            media_type: media_type.to_string(),
+            manifest: manifest.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            // Like ECR, which doesn't always report a size for manifest lists.
+            size: (media_type != INDEX_MEDIA_TYPE).then_some(manifest.len() as i64),
+            pushed_at: 1_700_000_000 + images.len() as i64,
+        });
+        digest
+    }
+
+    /// Hand out `<base_url>/<digest>` as the download URL of every blob.
+    pub fn serve_blobs_from(&self, base_url: &str) {
+        self.state.lock().unwrap().blob_url = Some(base_url.to_string());
+    }
+
+    /// Make the next call to `operation` fail with a `ThrottlingException`.
+    pub fn throttle(&self, operation: &'static str, retry_after: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.queue(operation, Fault::Throttle(retry_after));
+    }
+
+    /// Make the next call to `operation` fail to connect, without any response.
+    pub fn fail_dispatch(&self, operation: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        state.queue(operation, Fault::Dispatch);
+    }
+
+    /// How many times `operation` was called, including failed calls.
+    pub fn calls(&self, operation: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.calls.get(operation).copied().unwrap_or_default()
+    }
+
+    /// Number of image IDs requested by each `BatchGetImage` call.
+    pub fn batch_sizes(&self) -> Vec<usize> {
+        self.state.lock().unwrap().batch_sizes.clone()
+    }
+}
+
+fn response(status: u16) -> HttpResponse {
+    HttpResponse::new(StatusCode::try_from(status).unwrap(), SdkBody::empty())
+}
+
+fn throttled<E>(
+    error: fn(ErrorMetadata) -> E,
+    retry_after: Option<u64>,
+) -> SdkError<E, HttpResponse> {
+    let mut response = response(400);
+    if let Some(seconds) = retry_after {
+        response
+            .headers_mut()
+            .insert("retry-after", seconds.to_string());
+    }
+    let metadata = ErrorMetadata::builder()
+        .code("ThrottlingException")
+        .message("Rate exceeded")
+        .build();
+    SdkError::service_error(error(metadata), response)
+}
+
+fn unsupported<E>(error: fn(ErrorMetadata) -> E, operation: &str) -> SdkError<E, HttpResponse> {
+    let metadata = ErrorMetadata::builder()
+        .code("UnsupportedOperation")
+        .message(format!("{operation} is not faked"))
+        .build();
+    SdkError::service_error(error(metadata), response(400))
+}
+
+fn repository_not_found(name: &str) -> RepositoryNotFoundException {
+    RepositoryNotFoundException::builder()
+        .message(format!("The repository with name '{name}' does not exist"))
+        .build()
+}
+
+fn matches_id(image: &FakeImage, id: &ImageIdentifier) -> bool {
+    id.image_digest()
+        .is_none_or(|digest| digest == image.digest)
+        && id
+            .image_tag()
+            .is_none_or(|tag| image.tags.iter().any(|t| t == tag))
+}
+
+impl EcrApi for FakeEcr {
+    async fn describe_repositories(
+        &self,
+        input: DescribeRepositoriesInput,
+    ) -> EcrResult<DescribeRepositoriesOutput, DescribeRepositoriesError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(fault) = state.call("DescribeRepositories") {
+            return Err(fault.into_error(DescribeRepositoriesError::generic));
+        }
+        let start: usize = input.next_token.map_or(0, |token| token.parse().unwrap());
+        let page_size = input.max_results.unwrap_or(1000) as usize;
+        let names = state.repositories.keys().collect::<Vec<_>>();
+        let end = (start + page_size).min(names.len());
+        Ok(DescribeRepositoriesOutput::builder()
+            .set_repositories(Some(
+                names[start..end]
+                    .iter()
+                    .map(|name| Repository::builder().repository_name(*name).build())
+                    .collect(),
+            ))
+            .set_next_token((end < names.len()).then(|| end.to_string()))
+            .build())
+    }
+
+    async fn describe_images(
+        &self,
+        input: DescribeImagesInput,
+    ) -> EcrResult<DescribeImagesOutput, DescribeImagesError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(fault) = state.call("DescribeImages") {
+            return Err(fault.into_error(DescribeImagesError::generic));
+        }
+        let name = input.repository_name.unwrap_or_default();
+        let Some(images) = state.repositories.get(&name) else {
+            return Err(SdkError::service_error(
+                DescribeImagesError::RepositoryNotFoundException(repository_not_found(&name)),
+                response(400),
+            ));
+        };
+        let tag_status = input
+            .filter
+            .and_then(|filter| filter.tag_status)
+            .unwrap_or(TagStatus::Any);
+        let images = images
+            .iter()
+            .filter(|image| match tag_status {
+                TagStatus::Tagged => !image.tags.is_empty(),
+                TagStatus::Untagged => image.tags.is_empty(),
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+        let start: usize = input.next_token.map_or(0, |token| token.parse().unwrap());
+        let page_size = input.max_results.unwrap_or(1000) as usize;
+        let end = (start + page_size).min(images.len());
+        let details = images[start..end]
+            .iter()
+            .map(|image| {
+                ImageDetail::builder()
+                    .repository_name(&name)
+                    .image_digest(&image.digest)
+                    .set_image_tags((!image.tags.is_empty()).then(|| image.tags.clone()))
+                    .set_image_size_in_bytes(image.size)
+                    .image_pushed_at(DateTime::from_secs(image.pushed_at))
+                    .image_manifest_media_type(&image.media_type)
+                    .build()
+            })
+            .collect();
+        Ok(DescribeImagesOutput::builder()
+            .set_image_details(Some(details))
+            .set_next_token((end < images.len()).then(|| end.to_string()))
+            .build())
+    }
+
+    async fn batch_get_image(
+        &self,
+        input: BatchGetImageInput,
+    ) -> EcrResult<BatchGetImageOutput, BatchGetImageError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(fault) = state.call("BatchGetImage") {
+            return Err(fault.into_error(BatchGetImageError::generic));
+        }
+        let ids = input.image_ids.unwrap_or_default();
+        state.batch_sizes.push(ids.len());
+        let name = input.repository_name.unwrap_or_default();
+        let Some(images) = state.repositories.get(&name) else {
+            return Err(SdkError::service_error(
+                BatchGetImageError::RepositoryNotFoundException(repository_not_found(&name)),
+                response(400),
+            ));
+        };
+        let mut output = BatchGetImageOutput::builder();
+        for id in ids {
+            match images.iter().find(|image| matches_id(image, &id)) {
+                Some(image) => {
+                    output = output.images(
+                        Image::builder()
+                            .repository_name(&name)
+                            .image_id(id)
+                            .image_manifest(&image.manifest)
+                            .image_manifest_media_type(&image.media_type)
+                            .build(),
+                    )
+                }
+                None => {
+                    output = output.failures(
+                        ImageFailure::builder()
+                            .image_id(id)
+                            .failure_code(ImageFailureCode::ImageNotFound)
+                            .failure_reason("Requested image not found")
+                            .build(),
+                    )
+                }
+            }
+        }
+        Ok(output.build())
+    }
+
+    async fn get_download_url_for_layer(
+        &self,
+        input: GetDownloadUrlForLayerInput,
+    ) -> EcrResult<GetDownloadUrlForLayerOutput, GetDownloadUrlForLayerError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(fault) = state.call("GetDownloadUrlForLayer") {
+            return Err(fault.into_error(GetDownloadUrlForLayerError::generic));
+        }
+        let Some(blob_url) = &state.blob_url else {
+            return Err(unsupported(
+                GetDownloadUrlForLayerError::generic,
+                "GetDownloadUrlForLayer",
+            ));
+        };
+        let digest = input.layer_digest.unwrap_or_default();
+        Ok(GetDownloadUrlForLayerOutput::builder()
+            .download_url(format!("{blob_url}/{digest}"))
+            .layer_digest(digest)
+            .build())
+    }
+
+    async fn describe_image_scan_findings(
+        &self,
+        _input: DescribeImageScanFindingsInput,
+    ) -> EcrResult<DescribeImageScanFindingsOutput, DescribeImageScanFindingsError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(fault) = state.call("DescribeImageScanFindings") {
+            return Err(fault.into_error(DescribeImageScanFindingsError::generic));
+        }
+        Err(unsupported(
+            DescribeImageScanFindingsError::generic,
+            "DescribeImageScanFindings",
+        ))
+    }
+}
@@ -0,0 +1,76 @@
+use clap::ValueEnum;
+use ecr_dump::images::ImageWithManifests;
+use serde_json::{Map, Value};
+
+/// Fields that `--fields` can project an image record down to. Nested values such as
+/// layer sizes are flattened across every manifest of the image. The full record's key
+/// names are accepted as aliases, e.g. `repository_name` for `repo`.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+#[value(rename_all = "snake_case")]
+pub enum Field {
+    #[value(alias = "repository_name")]
+    Repo,
+    #[value(aliases = ["image_digest", "manifest_digest"])]
+    Digest,
+    #[value(alias = "manifest_type")]
+    Type,
+    #[value(alias = "image_tags")]
+    Tags,
+    #[value(alias = "image_pushed_at")]
+    PushedAt,
+    #[value(alias = "total_size")]
+    Size,
+    #[value(alias = "total_layer_count")]
+    LayerCount,
+    LayerSizes,
+    LayerDigests,
+    Region,
+    RepositoryUri,
+}
+
+impl Field {
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Repo => "repo",
+            Field::Digest => "digest",
+            Field::Type => "type",
+            Field::Tags => "tags",
+            Field::PushedAt => "pushed_at",
+            Field::Size => "size",
+            Field::LayerCount => "layer_count",
+            Field::LayerSizes => "layer_sizes",
+            Field::LayerDigests => "layer_digests",
+            Field::Region => "region",
+            Field::RepositoryUri => "repository_uri",
+        }
+    }
+
+    fn value(&self, image: &ImageWithManifests) -> Value {
+        let layers = || image.manifests.iter().flat_map(|m| m.content.layers());
+        match self {
+            Field::Repo => image.image.repository_name.clone().into(),
+            Field::Digest => image.image.manifest_digest.clone().into(),
+            Field::Type => image.image.manifest_type.to_string().into(),
+            Field::Tags => image.image.image_tags.clone().into(),
+            Field::PushedAt => image.image.image_pushed_at.to_rfc3339().into(),
+            Field::Size => image.stats.total_size.into(),
+            Field::LayerCount => image.stats.total_layer_count.into(),
+            Field::LayerSizes => layers().map(|l| l.size()).collect::<Vec<_>>().into(),
+            Field::LayerDigests => layers()
+                .map(|l| l.digest().to_string())
+                .collect::<Vec<_>>()
+                .into(),
+            Field::Region => image.region.clone().into(),
+            Field::RepositoryUri => image.repository_uri.clone().into(),
+        }
+    }
+}
+
+/// Build a JSON object holding only the requested fields, in the order they were given.
+pub fn project(image: &ImageWithManifests, fields: &[Field]) -> Value {
+    let map: Map<String, Value> = fields
+        .iter()
+        .map(|field| (field.name().to_string(), field.value(image)))
+        .collect();
+    Value::Object(map)
+}
@@ -0,0 +1,551 @@
+//! A small expression language for filtering images, e.g.
+//! `size > 1GB and not tagged and pushed_at < 2024-01-01`.
+//!
+//! ```text
+//! expr       := and ("or" and)*
+//! and        := unary ("and" unary)*
+//! unary      := "not" unary | "(" expr ")" | comparison | "tagged"
+//! comparison := field op value
+//! op         := "=" | "==" | "!=" | "<" | "<=" | ">" | ">=" | "~"
+//! ```
+//!
+//! | field       | values                                | operators                |
+//! |-------------|---------------------------------------|--------------------------|
+//! | `repo`      | string                                | `=` `!=` `~` (glob)      |
+//! | `tag`       | string, true if any tag matches       | `=` `!=` `~` (glob)      |
+//! | `type`      | `image`, `list`, `schema_v1` or `unknown` | `=` `!=`             |
+//! | `pushed_at` | `2024-01-01` or an RFC3339 timestamp  | all except `~`           |
+//! | `size`      | bytes, with optional `KB`/`MB`/`GB`/`TB` (or `KiB`...) suffix | all except `~` |
+//! | `tag_count` | integer                               | all except `~`           |
+//!
+//! `tagged` on its own is true for images with at least one tag. Strings containing
+//! spaces or operator characters can be quoted with `"` or `'`. `size` is the size ECR
+//! reports for the image, and images without one never match a size comparison.
+
+use crate::images::{ManifestType, RepositoryImage};
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, NaiveDate, Utc};
+use globset::{Glob, GlobMatcher};
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+
+#[derive(Debug, Clone)]
+pub struct FilterExpr {
+    source: String,
+    root: Node,
+}
+
+impl FilterExpr {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {token} after the end of the expression");
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, image: &RepositoryImage) -> bool {
+        self.root.eval(image)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Tagged,
+    Compare(Comparison),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Glob,
+}
+
+impl Op {
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        Some(match symbol {
+            "=" | "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            "~" => Op::Glob,
+            _ => return None,
+        })
+    }
+
+    fn compare(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+            Op::Glob => unreachable!("glob comparisons are rejected when parsing"),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum StringMatch {
+    Exact(String),
+    Glob(GlobMatcher),
+}
+
+impl Debug for StringMatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StringMatch::Exact(value) => write!(f, "{value:?}"),
+            StringMatch::Glob(matcher) => write!(f, "~{:?}", matcher.glob().glob()),
+        }
+    }
+}
+
+impl StringMatch {
+    fn is_match(&self, value: &str) -> bool {
+        match self {
+            StringMatch::Exact(expected) => expected == value,
+            StringMatch::Glob(matcher) => matcher.is_match(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Comparison {
+    Repo { negate: bool, value: StringMatch },
+    Tag { negate: bool, value: StringMatch },
+    Type { negate: bool, value: ManifestType },
+    PushedAt(Op, DateTime<Utc>),
+    Size(Op, i64),
+    TagCount(Op, usize),
+}
+
+impl Node {
+    fn eval(&self, image: &RepositoryImage) -> bool {
+        match self {
+            Node::And(left, right) => left.eval(image) && right.eval(image),
+            Node::Or(left, right) => left.eval(image) || right.eval(image),
+            Node::Not(inner) => !inner.eval(image),
+            Node::Tagged => !image.image_tags.is_empty(),
+            Node::Compare(comparison) => match comparison {
+                Comparison::Repo { negate, value } => {
+                    value.is_match(&image.repository_name) != *negate
+                }
+                Comparison::Tag { negate, value } => {
+                    image.image_tags.iter().any(|tag| value.is_match(tag)) != *negate
+                }
+                Comparison::Type { negate, value } => (image.manifest_type == *value) != *negate,
+                Comparison::PushedAt(op, date) => op.compare(image.image_pushed_at.cmp(date)),
+                Comparison::Size(op, size) => image
+                    .image_size_in_bytes
+                    .is_some_and(|image_size| op.compare(image_size.cmp(size))),
+                Comparison::TagCount(op, count) => op.compare(image.image_tags.len().cmp(count)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(String),
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::Quoted(value) => write!(f, "{value:?}"),
+            Token::Op(op) => write!(f, "`{op}`"),
+            Token::Open => f.write_str("`(`"),
+            Token::Close => f.write_str("`)`"),
+        }
+    }
+}
+
+const OPERATOR_CHARS: &[char] = &['=', '!', '<', '>', '~'];
+
+fn tokenize(source: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            tokens.push(Token::Open);
+        } else if c == ')' {
+            chars.next();
+            tokens.push(Token::Close);
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some(next) if next == c => break,
+                    Some(next) => value.push(next),
+                    None => bail!("Unterminated string starting {c}{value}"),
+                }
+            }
+            tokens.push(Token::Quoted(value));
+        } else if OPERATOR_CHARS.contains(&c) {
+            let mut op = String::new();
+            while let Some(&next) = chars.peek() {
+                if !OPERATOR_CHARS.contains(&next) {
+                    break;
+                }
+                op.push(next);
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        } else {
+            let mut word = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_whitespace()
+                    || next == '('
+                    || next == ')'
+                    || OPERATOR_CHARS.contains(&next)
+                {
+                    break;
+                }
+                word.push(next);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> anyhow::Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unexpected end of expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self) -> anyhow::Result<Node> {
+        let mut node = self.and()?;
+        while self.eat_keyword("or") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Node> {
+        let mut node = self.unary()?;
+        while self.eat_keyword("and") {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Node> {
+        if self.eat_keyword("not") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        match self.next()? {
+            Token::Open => {
+                let node = self.expr()?;
+                match self.next()? {
+                    Token::Close => Ok(node),
+                    token => bail!("Expected `)`, found {token}"),
+                }
+            }
+            Token::Word(field) if field.eq_ignore_ascii_case("tagged") => Ok(Node::Tagged),
+            Token::Word(field) => self.comparison(&field),
+            token => bail!("Expected a field, `not` or `(`, found {token}"),
+        }
+    }
+
+    fn comparison(&mut self, field: &str) -> anyhow::Result<Node> {
+        let op = match self.next()? {
+            Token::Op(symbol) => {
+                Op::from_symbol(&symbol).ok_or_else(|| anyhow!("Unknown operator `{symbol}`"))?
+            }
+            token => bail!("Expected an operator after `{field}`, found {token}"),
+        };
+        let value = match self.next()? {
+            Token::Word(value) | Token::Quoted(value) => value,
+            token => bail!("Expected a value after `{field}`, found {token}"),
+        };
+
+        let comparison = match field.to_ascii_lowercase().as_str() {
+            "repo" => Comparison::Repo {
+                negate: op == Op::Ne,
+                value: string_match(field, op, &value)?,
+            },
+            "tag" => Comparison::Tag {
+                negate: op == Op::Ne,
+                value: string_match(field, op, &value)?,
+            },
+            "type" => Comparison::Type {
+                negate: equality(field, op)? == Op::Ne,
+                value: match value.to_ascii_lowercase().as_str() {
+                    "image" => ManifestType::Image,
+                    "list" => ManifestType::List,
+                    "schema_v1" => ManifestType::SchemaV1,
+                    "unknown" => ManifestType::Unknown,
+                    _ => bail!(
+                        "`type` must be `image`, `list`, `schema_v1` or `unknown`, not {value:?}"
+                    ),
+                },
+            },
+            "pushed_at" => Comparison::PushedAt(ordered(field, op)?, parse_date(&value)?),
+            "size" => Comparison::Size(ordered(field, op)?, parse_size(&value)?),
+            "tag_count" => Comparison::TagCount(
+                ordered(field, op)?,
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid tag_count {value:?}"))?,
+            ),
+            _ => bail!(
+                "Unknown field `{field}`, expected one of repo, tag, type, pushed_at, size, tag_count or tagged"
+            ),
+        };
+        Ok(Node::Compare(comparison))
+    }
+}
+
+fn string_match(field: &str, op: Op, value: &str) -> anyhow::Result<StringMatch> {
+    Ok(match op {
+        Op::Eq | Op::Ne => StringMatch::Exact(value.to_string()),
+        Op::Glob => StringMatch::Glob(
+            Glob::new(value)
+                .with_context(|| format!("Invalid glob {value:?}"))?
+                .compile_matcher(),
+        ),
+        _ => bail!("`{field}` only supports `=`, `!=` and `~`"),
+    })
+}
+
+fn equality(field: &str, op: Op) -> anyhow::Result<Op> {
+    match op {
+        Op::Eq | Op::Ne => Ok(op),
+        _ => bail!("`{field}` only supports `=` and `!=`"),
+    }
+}
+
+fn ordered(field: &str, op: Op) -> anyhow::Result<Op> {
+    match op {
+        Op::Glob => bail!("`{field}` does not support `~`"),
+        _ => Ok(op),
+    }
+}
+
+fn parse_date(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .with_context(|| format!("Invalid date {value:?}, expected YYYY-MM-DD or RFC3339"))
+}
+
+fn parse_size(value: &str) -> anyhow::Result<i64> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid size {value:?}"))?;
+    let multiplier: f64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => bail!("Unknown size unit {unit:?} in {value:?}"),
+    };
+    Ok((number * multiplier) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(tags: &[&str], size: Option<i64>, manifest_type: ManifestType) -> RepositoryImage {
+        RepositoryImage {
+            repository_name: "team/app".to_string(),
+            manifest_digest: "sha256:abc".to_string(),
+            manifest_type,
+            image_tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            image_pushed_at: "2024-03-01T12:00:00Z".parse().unwrap(),
+            manifest_media_type: String::new(),
+            image_size_in_bytes: size,
+            last_recorded_pull_time: None,
+        }
+    }
+
+    fn matches(source: &str, image: &RepositoryImage) -> bool {
+        FilterExpr::parse(source).unwrap().matches(image)
+    }
+
+    fn error(source: &str) -> String {
+        FilterExpr::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let untagged = image(&[], Some(10), ManifestType::Image);
+        // `tagged or (size > 5 and tag_count = 0)`
+        assert!(matches("tagged or size > 5 and tag_count = 0", &untagged));
+        assert!(!matches(
+            "(tagged or size > 5) and tag_count = 1",
+            &untagged
+        ));
+        assert!(matches(
+            "tag_count = 1 and tagged or repo = team/app",
+            &untagged
+        ));
+    }
+
+    #[test]
+    fn not_and_parentheses() {
+        let tagged = image(&["latest"], Some(10), ManifestType::Image);
+        assert!(!matches("not tagged", &tagged));
+        assert!(matches("not not tagged", &tagged));
+        assert!(matches("not (tag = dev or size > 100)", &tagged));
+        // `(not tag = dev) or (tag = latest and not tagged)`
+        assert!(matches(
+            "not tag = dev or tag = latest and not tagged",
+            &tagged
+        ));
+        assert!(matches("NOT (TAGGED AND tag ~ 'v*')", &tagged));
+    }
+
+    #[test]
+    fn sizes_take_decimal_and_binary_suffixes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("2KB").unwrap(), 2_000);
+        assert_eq!(parse_size("1.5GB").unwrap(), 1_500_000_000);
+        assert_eq!(parse_size("1kib").unwrap(), 1024);
+        assert_eq!(parse_size("2MiB").unwrap(), 2 * 1024 * 1024);
+        let large = image(&[], Some(2_000_000_000), ManifestType::Image);
+        assert!(matches("size > 1GB", &large));
+        assert!(!matches("size > 2GiB", &large));
+        assert_eq!(error("size > 10XB"), "Unknown size unit \"XB\" in \"10XB\"");
+        assert_eq!(error("size > GB"), "Invalid size \"GB\"");
+    }
+
+    #[test]
+    fn compares_dates_and_timestamps() {
+        let image = image(&[], None, ManifestType::Image);
+        assert!(matches("pushed_at >= 2024-03-01", &image));
+        assert!(matches("pushed_at < 2024-03-02", &image));
+        assert!(!matches("pushed_at < 2024-03-01T12:00:00Z", &image));
+        assert!(matches("pushed_at = '2024-03-01T13:00:00+01:00'", &image));
+        assert_eq!(
+            error("pushed_at > yesterday"),
+            "Invalid date \"yesterday\", expected YYYY-MM-DD or RFC3339"
+        );
+        assert_eq!(
+            error("pushed_at ~ 2024*"),
+            "`pushed_at` does not support `~`"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_operators() {
+        assert_eq!(
+            error("digest = sha256:abc"),
+            "Unknown field `digest`, expected one of repo, tag, type, pushed_at, size, tag_count or tagged"
+        );
+        assert_eq!(error("tag <> latest"), "Unknown operator `<>`");
+        assert_eq!(
+            error("tag > latest"),
+            "`tag` only supports `=`, `!=` and `~`"
+        );
+        assert_eq!(error("type ~ list"), "`type` only supports `=` and `!=`");
+        assert_eq!(
+            error("type = blob"),
+            "`type` must be `image`, `list`, `schema_v1` or `unknown`, not \"blob\""
+        );
+    }
+
+    #[test]
+    fn rejects_trailing_and_missing_tokens() {
+        assert_eq!(
+            error("tagged tagged"),
+            "Unexpected `tagged` after the end of the expression"
+        );
+        assert_eq!(
+            error("tagged )"),
+            "Unexpected `)` after the end of the expression"
+        );
+        assert_eq!(error("(tagged"), "Unexpected end of expression");
+        assert_eq!(error("(tagged tagged"), "Expected `)`, found `tagged`");
+        assert_eq!(error("tagged and"), "Unexpected end of expression");
+        assert_eq!(error("repo ="), "Unexpected end of expression");
+        assert_eq!(
+            error("repo = (app)"),
+            "Expected a value after `repo`, found `(`"
+        );
+        assert_eq!(error("= app"), "Expected a field, `not` or `(`, found `=`");
+        assert_eq!(
+            error("tag = 'latest"),
+            "Unterminated string starting 'latest"
+        );
+    }
+
+    #[test]
+    fn lists_without_a_size_never_match_size_comparisons() {
+        let list = image(&["latest"], None, ManifestType::List);
+        assert!(matches("type = list", &list));
+        for source in ["size > 0", "size < 1TB", "size = 0", "size != 0"] {
+            assert!(!matches(source, &list), "{source}");
+        }
+        // Negating the comparison is the only way to match them.
+        assert!(matches("not size > 1GB", &list));
+    }
+}
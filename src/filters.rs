@@ -0,0 +1,212 @@
+use crate::filter_expr::FilterExpr;
+use crate::images::{ManifestType, RepositoryImage};
+use crate::repos::RepositoryName;
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use globset::GlobSet;
+use oci_spec::image::Descriptor;
+use std::collections::HashSet;
+use std::str::FromStr;
+use tracing::trace;
+
+/// Decides which listed images go on to be resolved. Applied in
+/// [`crate::images::ImageFetcher::fetch_images`], after repository filtering.
+#[derive(Debug, Clone, Default)]
+pub struct ImageFilter {
+    pub excluded_digests: DigestSet,
+    pub expression: Option<FilterExpr>,
+    /// Keep only images with a tag matching this. Untagged images never match.
+    pub tag_include: Option<GlobSet>,
+    /// Drop images with any tag matching this.
+    pub tag_exclude: Option<GlobSet>,
+    /// Keep only images pushed at or after this time.
+    pub pushed_after: Option<DateTime<Utc>>,
+    /// Keep only images pushed strictly before this time.
+    pub pushed_before: Option<DateTime<Utc>>,
+    /// Keep only images last pulled before this time.
+    pub stale_since: Option<DateTime<Utc>>,
+    /// With `stale_since`, also keep images ECR has no pull recorded for.
+    pub include_never_pulled: bool,
+}
+
+impl ImageFilter {
+    pub fn matches(&self, image: &RepositoryImage) -> bool {
+        if self
+            .excluded_digests
+            .contains(&image.repository_name, &image.manifest_digest)
+        {
+            trace!("Excluding {image}: digest is excluded");
+            return false;
+        }
+        if self
+            .pushed_after
+            .is_some_and(|after| image.image_pushed_at < after)
+            || self
+                .pushed_before
+                .is_some_and(|before| image.image_pushed_at >= before)
+        {
+            trace!("Excluding {image}: pushed outside the time window");
+            return false;
+        }
+        if let Some(stale_since) = self.stale_since {
+            let stale = match image.last_recorded_pull_time {
+                Some(pulled_at) => pulled_at < stale_since,
+                None => self.include_never_pulled,
+            };
+            if !stale {
+                trace!("Excluding {image}: pulled since {stale_since}");
+                return false;
+            }
+        }
+        if let Some(tag_include) = &self.tag_include {
+            if !image.image_tags.iter().any(|tag| tag_include.is_match(tag)) {
+                trace!("Excluding {image}: no tag matches the tag include filter");
+                return false;
+            }
+        }
+        if let Some(tag_exclude) = &self.tag_exclude {
+            if image.image_tags.iter().any(|tag| tag_exclude.is_match(tag)) {
+                trace!("Excluding {image}: a tag matches the tag exclude filter");
+                return false;
+            }
+        }
+        if let Some(expression) = &self.expression {
+            if !expression.matches(image) {
+                trace!("Excluding {image}: does not match the filter expression");
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// An `os/architecture[/variant]` platform, such as `linux/amd64` or `linux/arm64/v8`,
+/// that manifest list children are filtered by.
+///
+/// Children without a platform are treated as `unknown/unknown`, the platform BuildKit
+/// gives attestation manifests, so they are only kept when that is asked for. Nested
+/// indexes are always followed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformSpec {
+    pub os: String,
+    pub architecture: String,
+    /// Matches any variant if unset.
+    pub variant: Option<String>,
+}
+
+impl PlatformSpec {
+    pub fn matches(&self, descriptor: &Descriptor) -> bool {
+        let (os, architecture, variant) = match descriptor.platform() {
+            Some(platform) => (
+                platform.os().to_string(),
+                platform.architecture().to_string(),
+                platform.variant().as_deref(),
+            ),
+            None => ("unknown".to_string(), "unknown".to_string(), None),
+        };
+        self.os == os
+            && self.architecture == architecture
+            && (self.variant.is_none() || self.variant.as_deref() == variant)
+    }
+
+    /// Whether `descriptor` should be resolved with `platforms` selected, all if empty.
+    pub fn any_matches(platforms: &[Self], descriptor: &Descriptor) -> bool {
+        platforms.is_empty()
+            || ManifestType::from_media_type(&descriptor.media_type().to_string())
+                == ManifestType::List
+            || platforms
+                .iter()
+                .any(|platform| platform.matches(descriptor))
+    }
+}
+
+impl FromStr for PlatformSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(os), Some(architecture), variant, None)
+                if !os.is_empty() && !architecture.is_empty() =>
+            {
+                Ok(Self {
+                    os: os.to_string(),
+                    architecture: architecture.to_string(),
+                    variant: variant.filter(|v| !v.is_empty()).map(str::to_string),
+                })
+            }
+            _ => bail!("{s:?} is not a platform like linux/amd64 or linux/arm64/v8"),
+        }
+    }
+}
+
+/// A set of manifest digests, either matching in every repository (`sha256:...`) or
+/// scoped to a single one (`repo@sha256:...`).
+#[derive(Debug, Clone, Default)]
+pub struct DigestSet {
+    any_repository: HashSet<String>,
+    scoped: HashSet<(RepositoryName, String)>,
+}
+
+impl DigestSet {
+    /// Parse newline-separated digests, ignoring blank lines and `#` comments.
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut set = Self::default();
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (repository, digest) = match line.rsplit_once('@') {
+                Some((repository, digest)) => (Some(repository), digest),
+                None => (None, line),
+            };
+            if !is_digest(digest) || repository.is_some_and(str::is_empty) {
+                bail!(
+                    "Line {}: {line:?} is not a `sha256:...` or `repo@sha256:...` digest",
+                    idx + 1
+                );
+            }
+            match repository {
+                Some(repository) => {
+                    set.scoped
+                        .insert((repository.to_string(), digest.to_string()));
+                }
+                None => {
+                    set.any_repository.insert(digest.to_string());
+                }
+            }
+        }
+        Ok(set)
+    }
+
+    /// Add `digest`, scoped to `repository`.
+    pub fn insert(&mut self, repository: &str, digest: &str) {
+        self.scoped
+            .insert((repository.to_string(), digest.to_string()));
+    }
+
+    pub fn contains(&self, repository: &str, digest: &str) -> bool {
+        self.any_repository.contains(digest)
+            || self
+                .scoped
+                .contains(&(repository.to_string(), digest.to_string()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.any_repository.len() + self.scoped.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn is_digest(value: &str) -> bool {
+    match value.split_once(':') {
+        Some((algorithm, hex)) => {
+            !algorithm.is_empty() && !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
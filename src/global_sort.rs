@@ -0,0 +1,226 @@
+use crate::output::RecordWriter;
+use anyhow::Context;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{BufWriter, Write};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWrite, BufReader, Lines};
+use tracing::{debug, info};
+
+/// `(region, repository_name, manifest_digest)`. The region is empty unless dumping every
+/// region. Repository records use an empty digest so they sort ahead of their images.
+type SortKey = (String, String, String);
+
+/// Buffers records so the whole dump can be written ordered by [`SortKey`].
+///
+/// Up to `max_in_memory` serialized records are held in memory. Beyond that, each full
+/// buffer is sorted and spilled to a temporary file, and the files are merged when the
+/// dump completes, so memory stays bounded at the cost of roughly the output's size in
+/// temporary disk space. Spilling runs on the blocking thread pool, and merging reads
+/// the files asynchronously, so neither stalls the runtime.
+pub struct GlobalSorter {
+    max_in_memory: usize,
+    buffer: Vec<(SortKey, String)>,
+    runs: Vec<tokio::fs::File>,
+}
+
+impl GlobalSorter {
+    pub fn new(max_in_memory: usize) -> Self {
+        Self {
+            max_in_memory: max_in_memory.max(1),
+            buffer: vec![],
+            runs: vec![],
+        }
+    }
+
+    pub async fn push<T: Serialize>(
+        &mut self,
+        region: &str,
+        repository_name: &str,
+        digest: &str,
+        record: &T,
+    ) -> anyhow::Result<()> {
+        let key = (
+            region.to_string(),
+            repository_name.to_string(),
+            digest.to_string(),
+        );
+        self.buffer.push((key, serde_json::to_string(record)?));
+        if self.buffer.len() >= self.max_in_memory {
+            self.spill().await?;
+        }
+        Ok(())
+    }
+
+    /// Sort the buffer into a new temporary file. Records are written as
+    /// `<region>\t<repository>\t<digest>\t<json>`: none of these can contain a tab or
+    /// newline, as compact JSON escapes them.
+    async fn spill(&mut self) -> anyhow::Result<()> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let file = tokio::task::spawn_blocking(move || {
+            buffer.sort_unstable();
+            let mut file = tempfile::tempfile().context("Creating sort spill file")?;
+            let mut writer = BufWriter::new(&mut file);
+            for ((region, repository_name, digest), line) in buffer {
+                writeln!(writer, "{region}\t{repository_name}\t{digest}\t{line}")
+                    .context("Writing sort spill file")?;
+            }
+            writer.flush().context("Writing sort spill file")?;
+            drop(writer);
+            anyhow::Ok(file)
+        })
+        .await??;
+        self.runs.push(tokio::fs::File::from_std(file));
+        debug!("Spilled sorted run #{} to disk", self.runs.len());
+        Ok(())
+    }
+
+    /// Merge the in-memory buffer and any spilled runs into `writer`, in order.
+    pub async fn write_to<W: AsyncWrite + Unpin>(
+        mut self,
+        writer: &mut RecordWriter<W>,
+    ) -> anyhow::Result<()> {
+        self.buffer.sort_unstable();
+        if !self.runs.is_empty() {
+            info!(
+                "Merging {} sorted runs spilled to disk",
+                self.runs.len() + 1
+            );
+        }
+        let mut sources = vec![Run::Memory(self.buffer.into_iter())];
+        for mut file in self.runs {
+            file.rewind().await.context("Rewinding sort spill file")?;
+            sources.push(Run::Spilled(BufReader::new(file).lines()));
+        }
+
+        let mut heap = BinaryHeap::new();
+        for (idx, source) in sources.iter_mut().enumerate() {
+            if let Some(entry) = source.next().await? {
+                heap.push(Reverse((entry, idx)));
+            }
+        }
+        while let Some(Reverse(((_, line), idx))) = heap.pop() {
+            writer.write_line(&line).await?;
+            if let Some(entry) = sources[idx].next().await? {
+                heap.push(Reverse((entry, idx)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A sorted run of records being merged.
+enum Run {
+    Memory(std::vec::IntoIter<(SortKey, String)>),
+    Spilled(Lines<BufReader<tokio::fs::File>>),
+}
+
+impl Run {
+    async fn next(&mut self) -> anyhow::Result<Option<(SortKey, String)>> {
+        let lines = match self {
+            Run::Memory(records) => return Ok(records.next()),
+            Run::Spilled(lines) => lines,
+        };
+        let Some(line) = lines.next_line().await.context("Reading sort spill file")? else {
+            return Ok(None);
+        };
+        let mut parts = line.splitn(4, '\t');
+        let (Some(region), Some(repository_name), Some(digest), Some(record)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("Corrupt line in sort spill file");
+        };
+        Ok(Some((
+            (
+                region.to_string(),
+                repository_name.to_string(),
+                digest.to_string(),
+            ),
+            record.to_string(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::SinkFailure;
+    use serde_json::json;
+
+    async fn sorted(sorter: GlobalSorter) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sorted.jsonl");
+        let mut writer = RecordWriter::new(SinkFailure::Abort);
+        writer.add(
+            "sorted".to_string(),
+            tokio::fs::File::create(&path).await.unwrap(),
+        );
+        sorter.write_to(&mut writer).await.unwrap();
+        writer.finish().await.unwrap();
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        contents.lines().map(str::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn merges_every_spilled_run() {
+        let mut sorter = GlobalSorter::new(3);
+        for i in [7, 2, 9, 4, 0, 5, 8, 1, 6, 3] {
+            let digest = format!("sha256:{i}");
+            sorter
+                .push("", "app", &digest, &json!({"digest": digest}))
+                .await
+                .unwrap();
+        }
+        // Three full runs spilled, one record left in memory.
+        assert_eq!(sorter.runs.len(), 3);
+        assert_eq!(sorter.buffer.len(), 1);
+
+        let expected = (0..10)
+            .map(|i| json!({"digest": format!("sha256:{i}")}).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(sorted(sorter).await, expected);
+    }
+
+    #[tokio::test]
+    async fn orders_by_region_then_repository() {
+        let mut sorter = GlobalSorter::new(2);
+        let records = [
+            ("us-east-1", "web", "sha256:1"),
+            ("eu-west-1", "web", "sha256:2"),
+            ("us-east-1", "api", "sha256:3"),
+            ("eu-west-1", "api", ""),
+            ("eu-west-1", "api", "sha256:4"),
+        ];
+        for (region, repository, digest) in records {
+            let record = json!({"region": region, "repository": repository, "digest": digest});
+            sorter
+                .push(region, repository, digest, &record)
+                .await
+                .unwrap();
+        }
+
+        let order = sorted(sorter)
+            .await
+            .iter()
+            .map(|line| {
+                let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                format!(
+                    "{}/{}/{}",
+                    record["region"].as_str().unwrap(),
+                    record["repository"].as_str().unwrap(),
+                    record["digest"].as_str().unwrap()
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                "eu-west-1/api/",
+                "eu-west-1/api/sha256:4",
+                "eu-west-1/web/sha256:2",
+                "us-east-1/api/sha256:3",
+                "us-east-1/web/sha256:1",
+            ]
+        );
+    }
+}
@@ -1,48 +1,141 @@
+use crate::blob_cache::BlobCache;
+use crate::ecr_api::{EcrApi, EcrResult};
+use crate::filters::{ImageFilter, PlatformSpec};
+use crate::metrics::DumpMetrics;
 use crate::progress::{set_span_progress, span_set_spinner};
+use crate::rate_limit::RateLimiter;
 use crate::repos::RepositoryName;
+use crate::retry::RetryPolicy;
+use crate::scan_findings::ScanSummary;
+use crate::validation::validate_image_manifest;
 use anyhow::{bail, Context};
+use aws_sdk_ecr::error::SdkError;
+use aws_sdk_ecr::operation::batch_get_image::BatchGetImageInput;
+use aws_sdk_ecr::operation::describe_image_scan_findings::DescribeImageScanFindingsInput;
+use aws_sdk_ecr::operation::describe_images::{
+    DescribeImagesError, DescribeImagesInput, DescribeImagesOutput,
+};
+use aws_sdk_ecr::operation::get_download_url_for_layer::GetDownloadUrlForLayerInput;
 use aws_sdk_ecr::types::{DescribeImagesFilter, ImageDetail, ImageIdentifier, TagStatus};
 use aws_sdk_ecr::Client;
 use aws_smithy_types_convert::date_time::DateTimeExt;
 use chrono::{DateTime, Utc};
+use futures_util::future;
 use futures_util::stream::{self as stream, StreamExt};
 use futures_util::TryStreamExt;
 use itertools::Itertools;
-use oci_spec::image::{Descriptor, ImageIndex, ImageManifest};
-use serde::Serialize;
-use std::collections::HashMap;
+use oci_spec::image::{Descriptor, ImageConfiguration, ImageIndex, ImageManifest};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use tracing::{debug, instrument, trace};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::{debug, instrument, trace, warn, Span};
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 
-#[derive(Debug, Hash, Eq, PartialEq, Copy, Clone, strum::Display, Serialize)]
+#[derive(
+    Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, strum::Display, Serialize, Deserialize,
+)]
 pub enum ManifestType {
     Image,
     List,
+    /// A legacy Docker schema 1 manifest. These images are kept with no `manifests`,
+    /// since schema 1 has no layer sizes or config blob, and a `schema_v1` summary of the
+    /// manifest's name, tag, architecture and layer count instead. Everything
+    /// `DescribeImages` reports (tags, push and pull times, size) is still recorded.
+    SchemaV1,
+    /// A media type we can't parse, such as an artifact. These images are kept, with no
+    /// manifests, and their media type recorded.
+    Unknown,
 }
 
 impl ManifestType {
-    fn from_str(s: &str) -> Option<Self> {
+    pub(crate) fn from_media_type(s: &str) -> Self {
         match s {
             "application/vnd.oci.image.manifest.v1+json"
-            | "application/vnd.docker.distribution.manifest.v2+json" => Some(Self::Image),
+            | "application/vnd.docker.distribution.manifest.v2+json" => Self::Image,
             "application/vnd.oci.image.index.v1+json"
-            | "application/vnd.docker.distribution.manifest.list.v2+json" => Some(Self::List),
-            _ => None,
+            | "application/vnd.docker.distribution.manifest.list.v2+json" => Self::List,
+            "application/vnd.docker.distribution.manifest.v1+json"
+            | "application/vnd.docker.distribution.manifest.v1+prettyjws" => Self::SchemaV1,
+            _ => Self::Unknown,
         }
     }
 }
 
+/// What is recorded from a Docker schema 1 manifest.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchemaV1Summary {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    pub layer_count: usize,
+}
+
+impl SchemaV1Summary {
+    fn parse(manifest: &str) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Manifest {
+            name: Option<String>,
+            tag: Option<String>,
+            architecture: Option<String>,
+            #[serde(default)]
+            fs_layers: Vec<serde::de::IgnoredAny>,
+        }
+        let manifest: Manifest = serde_json::from_str(manifest)?;
+        Ok(Self {
+            name: manifest.name,
+            tag: manifest.tag,
+            architecture: manifest.architecture,
+            layer_count: manifest.fs_layers.len(),
+        })
+    }
+}
+
+fn is_known_media_type(media_type: &str) -> bool {
+    ManifestType::from_media_type(media_type) != ManifestType::Unknown
+}
+
 pub type ManifestDigest = String;
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone, Serialize)]
+/// Version of the image record format, written as `schema_version`. Bumped when a
+/// field is removed or changes meaning; new fields don't change it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Config blob media types holding an [`ImageConfiguration`]. Artifacts such as Helm
+/// charts and signatures use their own.
+const IMAGE_CONFIG_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.config.v1+json",
+    "application/vnd.docker.container.image.v1+json",
+];
+
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct RepositoryImage {
-    repository_name: RepositoryName,
-    manifest_digest: ManifestDigest,
-    manifest_type: ManifestType,
+    pub repository_name: RepositoryName,
+    pub manifest_digest: ManifestDigest,
+    pub manifest_type: ManifestType,
     #[serde(rename = "tags")]
-    image_tags: Vec<String>,
-    image_pushed_at: DateTime<Utc>,
+    pub image_tags: Vec<String>,
+    pub image_pushed_at: DateTime<Utc>,
+    /// The exact media type ECR reports for the manifest. Only serialized for
+    /// [`ManifestType::Unknown`] images.
+    #[serde(default, skip_serializing_if = "is_known_media_type")]
+    pub manifest_media_type: String,
+    /// Storage size ECR reports for the image (`imageSizeInBytes`). ECR doesn't always
+    /// report one for manifest lists, so these get the sum of their platform images'
+    /// sizes when every platform image was listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_size_in_bytes: Option<i64>,
+    /// When the image was last pulled, as far as ECR has recorded.
+    #[serde(skip)]
+    pub last_recorded_pull_time: Option<DateTime<Utc>>,
 }
 
 impl Display for RepositoryImage {
@@ -56,38 +149,192 @@ impl Display for RepositoryImage {
 }
 
 impl RepositoryImage {
+    /// Returns `None` for details missing a required field. An image whose push time
+    /// can't be represented is also skipped, with a warning.
     pub fn from_image_detail(detail: ImageDetail) -> Option<Self> {
-        if let Some(manifest_type) = ManifestType::from_str(detail.image_manifest_media_type()?) {
-            Some(Self {
-                repository_name: detail.repository_name?,
-                manifest_digest: detail.image_digest?,
-                manifest_type,
-                image_tags: detail.image_tags.unwrap_or_default(),
-                image_pushed_at: detail.image_pushed_at?.to_chrono_utc().unwrap(),
-            })
-        } else {
-            None
-        }
+        let manifest_media_type = detail.image_manifest_media_type()?.to_string();
+        let image_pushed_at = match detail.image_pushed_at?.to_chrono_utc() {
+            Ok(pushed_at) => pushed_at,
+            Err(e) => {
+                warn!(
+                    "Skipping image {} in {}: invalid push time: {e}",
+                    detail.image_digest.as_deref().unwrap_or_default(),
+                    detail.repository_name.as_deref().unwrap_or_default()
+                );
+                return None;
+            }
+        };
+        Some(Self {
+            repository_name: detail.repository_name?,
+            manifest_digest: detail.image_digest?,
+            manifest_type: ManifestType::from_media_type(&manifest_media_type),
+            image_tags: detail.image_tags.unwrap_or_default(),
+            image_pushed_at,
+            manifest_media_type,
+            image_size_in_bytes: detail.image_size_in_bytes,
+            last_recorded_pull_time: detail
+                .last_recorded_pull_time
+                .and_then(|time| time.to_chrono_utc().ok()),
+        })
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ImageManifestWithDescriptor {
     pub content: ImageManifest,
     pub descriptor: Option<Descriptor>,
+    /// The manifest's platform, from its manifest list descriptor, or from its config
+    /// blob when `fetch_config` is enabled. Unset for a single image without a config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// Schema problems found when `strict_manifests` is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub validation_issues: Vec<String>,
+    /// Set by [`merge_standalone_children`] when this manifest list child was also listed
+    /// as an image in its own right, holding that image's tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standalone_tags: Option<Vec<String>>,
+    /// Compression of each layer, in the same order as the manifest's layers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layer_compression: Vec<LayerCompression>,
+    /// Compressed size of this manifest's layers plus its config blob.
+    #[serde(default)]
+    pub total_layer_bytes: i64,
+    #[serde(default)]
+    pub layer_count: usize,
+    /// Read from the config blob when `fetch_config` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_config: Option<ImageConfigSummary>,
+    /// The manifest exactly as ECR returned it, when `include_raw_manifest` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_manifest: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+/// The platform and creation date recorded in an image's config blob.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageConfigSummary {
+    pub architecture: String,
+    pub os: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+}
+
+impl From<ImageConfiguration> for ImageConfigSummary {
+    fn from(config: ImageConfiguration) -> Self {
+        Self {
+            architecture: config.architecture().to_string(),
+            os: config.os().to_string(),
+            variant: config.variant().clone(),
+            created: config.created().clone(),
+        }
+    }
+}
+
+/// A layer's compression, as given by its media type.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, strum::Display, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum LayerCompression {
+    Gzip,
+    Zstd,
+    Uncompressed,
+    /// Not a tar layer, such as an artifact blob.
+    Unknown,
+}
+
+impl LayerCompression {
+    pub fn from_media_type(media_type: &str) -> Self {
+        if media_type.ends_with("+gzip") || media_type.ends_with(".tar.gzip") {
+            Self::Gzip
+        } else if media_type.ends_with("+zstd") {
+            Self::Zstd
+        } else if media_type.ends_with(".tar") {
+            Self::Uncompressed
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ImageStats {
+    /// Number of layers across the image's manifests, counting a layer once per manifest
+    /// that uses it.
     pub total_layer_count: usize,
+    /// The compressed sizes of the layers across the image's manifests, counted the same
+    /// way. Configs aren't included.
     pub total_size: usize,
+    /// What ECR bills for: `imageSizeInBytes` from `DescribeImages`, or the sum over the
+    /// platform images of a manifest list without one, the same as `image_size_in_bytes`.
+    #[serde(default)]
+    pub registry_size_bytes: Option<i64>,
+    /// `total_size`, named to pair with `registry_size_bytes` when reconciling the two.
+    /// They differ because of layer sharing and how ECR accounts for compression.
+    #[serde(default)]
+    pub manifest_sum_size_bytes: usize,
+}
+
+/// Whether an image listed by `DescribeImages` could be fetched with `BatchGetImage`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionStatus {
+    Resolved,
+    /// Listed by `DescribeImages`, but `BatchGetImage` returned nothing for it.
+    Missing,
+}
+
+impl ResolutionStatus {
+    fn resolved() -> Self {
+        Self::Resolved
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ImageWithManifests {
+    /// [`SCHEMA_VERSION`] for records written by this version. Records written before
+    /// it was added read as 0.
+    #[serde(default)]
+    pub schema_version: u32,
     pub image: RepositoryImage,
+    #[serde(default = "ResolutionStatus::resolved")]
+    pub resolution_status: ResolutionStatus,
     pub manifests: Vec<ImageManifestWithDescriptor>,
     pub stats: ImageStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// `<registry>.dkr.ecr.<region>.amazonaws.com/<repository>`, when the caller sets it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository_uri: Option<String>,
+    /// Number of platform manifests in a manifest list, counted before they are resolved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child_count: Option<usize>,
+    /// Whole days since the image was last pulled, when the caller computes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_since_pull: Option<i64>,
+    /// When the record was written, if the caller stamps it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emitted_at: Option<DateTime<Utc>>,
+    /// Set for [`ManifestType::SchemaV1`] images.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_v1: Option<SchemaV1Summary>,
+    /// The image's latest vulnerability scan, when `scan_findings` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_findings: Option<ScanSummary>,
+    /// Digests of this image's manifests whose content hashed to something else, when
+    /// `verify_digests` is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub digest_mismatches: Vec<ManifestDigest>,
+    /// Size in bytes of the image's top-level manifest, when it was fetched.
+    #[serde(skip)]
+    pub manifest_size: Option<usize>,
 }
 
 impl ImageWithManifests {
@@ -99,25 +346,218 @@ impl ImageWithManifests {
             .map(|v| v.size())
             .sum::<i64>();
         Self {
+            schema_version: SCHEMA_VERSION,
             image,
+            resolution_status: ResolutionStatus::Resolved,
             manifests,
             stats: ImageStats {
                 total_layer_count,
                 total_size: total_size as usize,
+                // Set by `resolve_images`, once manifest lists have their platforms' sizes.
+                registry_size_bytes: None,
+                manifest_sum_size_bytes: total_size as usize,
             },
+            region: None,
+            repository_uri: None,
+            child_count: None,
+            days_since_pull: None,
+            emitted_at: None,
+            schema_v1: None,
+            scan_findings: None,
+            digest_mismatches: vec![],
+            manifest_size: None,
         }
     }
+
+    pub fn missing(image: RepositoryImage) -> Self {
+        Self {
+            resolution_status: ResolutionStatus::Missing,
+            ..Self::new(image, vec![])
+        }
+    }
+}
+
+/// Fold images that are also children of a manifest list in `images` into that list's
+/// record, so the same manifest is not reported (and counted) twice. The child manifest
+/// keeps the standalone image's tags in `standalone_tags`.
+pub fn merge_standalone_children(images: Vec<ImageWithManifests>) -> Vec<ImageWithManifests> {
+    let child_digests: HashSet<String> = images
+        .iter()
+        .filter(|image| image.image.manifest_type == ManifestType::List)
+        .flat_map(|image| &image.manifests)
+        .filter_map(|manifest| manifest.descriptor.as_ref())
+        .map(|descriptor| descriptor.digest().to_string())
+        .collect();
+    let (standalone, mut images): (Vec<_>, Vec<_>) = images.into_iter().partition(|image| {
+        image.image.manifest_type == ManifestType::Image
+            && child_digests.contains(&image.image.manifest_digest)
+    });
+    let standalone_tags: HashMap<_, _> = standalone
+        .into_iter()
+        .map(|image| (image.image.manifest_digest, image.image.image_tags))
+        .collect();
+    for manifest in images.iter_mut().flat_map(|image| &mut image.manifests) {
+        if let Some(descriptor) = &manifest.descriptor {
+            if let Some(tags) = standalone_tags.get(descriptor.digest()) {
+                manifest.standalone_tags = Some(tags.clone());
+            }
+        }
+    }
+    debug!(
+        "Merged {} standalone images into their manifest lists",
+        standalone_tags.len()
+    );
+    images
+}
+
+/// Settings shared by every [`ImageFetcher`] in a dump.
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    /// Check each image manifest against the OCI schema, recording unexpected or
+    /// missing fields on the manifest instead of silently ignoring them.
+    pub strict_manifests: bool,
+    pub retry: RetryPolicy,
+    pub filter: Arc<ImageFilter>,
+    /// Write every manifest exactly as returned by ECR to `<dir>/<repo>/<digest>.json`.
+    pub raw_manifest_dir: Option<PathBuf>,
+    /// If listing a repository fails partway through, keep the images listed so far
+    /// instead of failing the repository, unless it no longer exists.
+    pub continue_on_error: bool,
+    /// The account whose registry to read, if not the caller's own.
+    pub registry_id: Option<String>,
+    /// Only list tagged or untagged images. Lists both if unset.
+    pub tag_status: Option<TagStatus>,
+    /// Download each manifest's config blob to record its platform and creation date.
+    /// Costs a `GetDownloadUrlForLayer` call and a download per distinct config. Only
+    /// image configs are fetched, not those of artifacts such as Helm charts, and a
+    /// config that can't be fetched is left unset with a warning.
+    pub fetch_config: bool,
+    /// Where downloaded config blobs are cached between runs.
+    pub blob_cache: Option<BlobCache>,
+    /// How many levels of manifest lists to follow when a list contains another list.
+    /// Lists nested deeper than this are skipped with a warning.
+    pub max_index_depth: usize,
+    /// Fail the repository if `BatchGetImage` couldn't return any of its manifests,
+    /// instead of recording the failures and carrying on.
+    pub fail_on_missing: bool,
+    /// Shared limit on the rate of ECR requests, applied before every call.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Only resolve manifest list children for these platforms, or all if empty.
+    pub platforms: Vec<PlatformSpec>,
+    /// Record a summary of each image's latest scan. Costs a
+    /// `DescribeImageScanFindings` call per image.
+    pub scan_findings: bool,
+    /// Check that every `sha256` manifest returned by `BatchGetImage` hashes to its
+    /// digest, recording mismatches on the image.
+    pub verify_digests: bool,
+    /// Keep each image manifest's original JSON as `raw_manifest`.
+    pub include_raw_manifest: bool,
+    /// Totals updated as images are fetched, shared by every clone of these options.
+    pub metrics: Arc<DumpMetrics>,
 }
 
-pub struct ImageFetcher {
-    client: Client,
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            strict_manifests: false,
+            retry: RetryPolicy::default(),
+            filter: Arc::default(),
+            raw_manifest_dir: None,
+            continue_on_error: false,
+            registry_id: None,
+            tag_status: None,
+            fetch_config: false,
+            blob_cache: None,
+            max_index_depth: 3,
+            fail_on_missing: false,
+            rate_limiter: None,
+            platforms: vec![],
+            scan_findings: false,
+            verify_digests: false,
+            include_raw_manifest: false,
+            metrics: Arc::default(),
+        }
+    }
+}
+
+pub struct ImageFetcher<C = Client> {
+    client: C,
     repo_name: RepositoryName,
     page_size: i32,
     chunk_size: usize,
     pub concurrency: usize,
+    pub options: FetchOptions,
+    api_calls: ApiCallCounters,
+    partial: AtomicBool,
+    failures: Mutex<Vec<ImageFailure>>,
+    digest_mismatches: Mutex<HashSet<ManifestDigest>>,
+}
+
+/// A manifest `BatchGetImage` couldn't return, such as one deleted after it was listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageFailure {
+    pub digest: Option<ManifestDigest>,
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Display for ImageFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.digest.as_deref().unwrap_or("an image"),
+            self.code
+        )?;
+        if let Some(reason) = &self.reason {
+            write!(f, " ({reason})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of ECR API calls made, including retries.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct ApiCallCounts {
+    pub describe_images: usize,
+    pub batch_get_image: usize,
+    pub get_download_url_for_layer: usize,
+    pub describe_image_scan_findings: usize,
+}
+
+impl ApiCallCounts {
+    pub fn total(&self) -> usize {
+        self.describe_images
+            + self.batch_get_image
+            + self.get_download_url_for_layer
+            + self.describe_image_scan_findings
+    }
+}
+
+impl AddAssign for ApiCallCounts {
+    fn add_assign(&mut self, rhs: Self) {
+        self.describe_images += rhs.describe_images;
+        self.batch_get_image += rhs.batch_get_image;
+        self.get_download_url_for_layer += rhs.get_download_url_for_layer;
+        self.describe_image_scan_findings += rhs.describe_image_scan_findings;
+    }
+}
+
+#[derive(Debug, Default)]
+struct ApiCallCounters {
+    describe_images: AtomicUsize,
+    batch_get_image: AtomicUsize,
+    get_download_url_for_layer: AtomicUsize,
+    describe_image_scan_findings: AtomicUsize,
+}
+
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
 }
 
-impl Display for ImageFetcher {
+impl<C> Display for ImageFetcher<C> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(&self.repo_name)
     }
@@ -128,28 +568,33 @@ struct ResolvedManifest {
     media_type: String,
 }
 
-type ManifestsAndDescriptors<'a> = (
-    Vec<ImageWithManifests>,
-    Vec<(&'a RepositoryImage, Vec<Descriptor>)>,
+/// An image whose manifest is an index, with the child manifests still to be resolved.
+#[derive(Debug)]
+pub struct ManifestList<'a> {
+    pub image: &'a RepositoryImage,
+    pub descriptors: Vec<Descriptor>,
+    pub manifest_size: usize,
+}
+
+type ManifestsAndDescriptors<'a> = (Vec<ImageWithManifests>, Vec<ManifestList<'a>>);
+
+type ResolvedChild<'a> = (
+    (&'a RepositoryImage, Descriptor),
+    ManifestDigest,
+    ResolvedManifest,
 );
 
-impl ImageFetcher {
-    #[allow(dead_code)]
-    pub fn new(client: Client, repo_name: RepositoryName) -> Self {
+impl<C: EcrApi> ImageFetcher<C> {
+    pub fn new(client: C, repo_name: RepositoryName) -> Self {
         Self::new_with_config(client, repo_name, 1000, 100, 10)
     }
 
-    #[allow(dead_code)]
-    pub fn new_with_concurrency(
-        client: Client,
-        repo_name: RepositoryName,
-        concurrency: usize,
-    ) -> Self {
+    pub fn new_with_concurrency(client: C, repo_name: RepositoryName, concurrency: usize) -> Self {
         Self::new_with_config(client, repo_name, 1000, 100, concurrency)
     }
 
     pub fn new_with_config(
-        client: Client,
+        client: C,
         repo_name: RepositoryName,
         page_size: i32,
         chunk_size: usize,
@@ -161,35 +606,181 @@ impl ImageFetcher {
             page_size,
             chunk_size,
             concurrency,
+            options: FetchOptions::default(),
+            api_calls: ApiCallCounters::default(),
+            partial: AtomicBool::new(false),
+            failures: Mutex::default(),
+            digest_mismatches: Mutex::default(),
         }
     }
 
-    #[instrument(skip_all, fields(repo = %self))]
-    pub async fn fetch_images(&self) -> anyhow::Result<Vec<RepositoryImage>> {
-        let mut image_details = vec![];
-        let span = span_set_spinner();
-        let mut stream = self
-            .client
-            .describe_images()
+    /// The ECR API calls this fetcher has made so far.
+    pub fn api_calls(&self) -> ApiCallCounts {
+        ApiCallCounts {
+            describe_images: self.api_calls.describe_images.load(Ordering::Relaxed),
+            batch_get_image: self.api_calls.batch_get_image.load(Ordering::Relaxed),
+            get_download_url_for_layer: self
+                .api_calls
+                .get_download_url_for_layer
+                .load(Ordering::Relaxed),
+            describe_image_scan_findings: self
+                .api_calls
+                .describe_image_scan_findings
+                .load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether `fetch_images` stopped early on an error and returned only some images.
+    pub fn is_partial(&self) -> bool {
+        self.partial.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the rate limiter, if any, then count the call about to be made.
+    async fn start_call(&self, counter: &AtomicUsize) {
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.options.metrics.add_api_call();
+    }
+
+    /// The manifests `BatchGetImage` has failed to return so far.
+    pub fn failures(&self) -> Vec<ImageFailure> {
+        self.failures.lock().unwrap().clone()
+    }
+
+    fn parse_image_manifest(
+        &self,
+        resolved_manifest: &ResolvedManifest,
+        descriptor: Option<Descriptor>,
+    ) -> anyhow::Result<ImageManifestWithDescriptor> {
+        let content: ImageManifest = serde_json::from_str(&resolved_manifest.manifest)?;
+        let validation_issues = if self.options.strict_manifests {
+            validate_image_manifest(&resolved_manifest.manifest)
+        } else {
+            vec![]
+        };
+        let layer_compression = content
+            .layers()
+            .iter()
+            .map(|layer| LayerCompression::from_media_type(&layer.media_type().to_string()))
+            .collect();
+        let total_layer_bytes = content
+            .layers()
+            .iter()
+            .map(|layer| layer.size())
+            .sum::<i64>()
+            + content.config().size();
+        let layer_count = content.layers().len();
+        let platform = descriptor.as_ref().and_then(|d| d.platform().as_ref());
+        Ok(ImageManifestWithDescriptor {
+            os: platform.map(|p| p.os().to_string()),
+            architecture: platform.map(|p| p.architecture().to_string()),
+            variant: platform.and_then(|p| p.variant().clone()),
+            content,
+            descriptor,
+            validation_issues,
+            standalone_tags: None,
+            layer_compression,
+            total_layer_bytes,
+            layer_count,
+            image_config: None,
+            raw_manifest: self
+                .options
+                .include_raw_manifest
+                .then(|| resolved_manifest.manifest.clone()),
+        })
+    }
+
+    async fn describe_images_page(
+        &self,
+        next_token: Option<String>,
+    ) -> EcrResult<DescribeImagesOutput, DescribeImagesError> {
+        let input = DescribeImagesInput::builder()
+            .set_registry_id(self.options.registry_id.clone())
             .set_repository_name(Some(self.repo_name.clone()))
             .set_max_results(Some(self.page_size))
+            .set_next_token(next_token)
             .filter(
                 DescribeImagesFilter::builder()
-                    .set_tag_status(Some(TagStatus::Any))
+                    .set_tag_status(Some(
+                        self.options.tag_status.clone().unwrap_or(TagStatus::Any),
+                    ))
                     .build(),
             )
-            .into_paginator()
-            .items()
-            .send();
+            .build()
+            .map_err(SdkError::construction_failure)?;
+        self.options
+            .retry
+            .retry("DescribeImages", || async {
+                self.start_call(&self.api_calls.describe_images).await;
+                self.client.describe_images(input.clone()).await
+            })
+            .await
+    }
 
-        while let Some(item) = stream.next().await {
-            image_details.push(item?);
-            span.pb_inc(1);
+    #[instrument(skip_all, fields(repo = %self))]
+    pub async fn count_images(&self) -> anyhow::Result<usize> {
+        let mut count = 0;
+        let mut next_token = None;
+        loop {
+            let page = self.describe_images_page(next_token).await?;
+            count += page
+                .image_details
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(RepositoryImage::from_image_detail)
+                .filter(|image| self.options.filter.matches(image))
+                .count();
+            next_token = page.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    #[instrument(skip_all, fields(repo = %self))]
+    pub async fn fetch_images(&self) -> anyhow::Result<Vec<RepositoryImage>> {
+        let mut image_details = vec![];
+        let span = span_set_spinner();
+        let mut next_token = None;
+
+        loop {
+            let page = match self.describe_images_page(next_token).await {
+                Ok(page) => page,
+                // A deleted repository is left for the caller to skip rather than dumped as
+                // an empty partial one.
+                Err(e)
+                    if self.options.continue_on_error
+                        && !e.as_service_error().is_some_and(
+                            DescribeImagesError::is_repository_not_found_exception,
+                        ) =>
+                {
+                    warn!(
+                        "Listing {self} failed after {} images, continuing with those: {}",
+                        image_details.len(),
+                        aws_sdk_ecr::error::DisplayErrorContext(&e)
+                    );
+                    self.partial.store(true, Ordering::Relaxed);
+                    self.options.metrics.add_errors(1);
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let details = page.image_details.unwrap_or_default();
+            span.pb_inc(details.len() as u64);
+            image_details.extend(details);
+            next_token = page.next_token;
+            if next_token.is_none() {
+                break;
+            }
         }
 
         Ok(image_details
             .into_iter()
             .filter_map(RepositoryImage::from_image_detail)
+            .filter(|image| self.options.filter.matches(image))
             .collect())
     }
 
@@ -216,26 +807,321 @@ impl ImageFetcher {
                 "Resolved {} images from images with manifest lists",
                 manifest_list_resolved.len()
             );
+            let sizes: HashMap<_, _> = images
+                .iter()
+                .filter_map(|image| Some((&image.manifest_digest, image.image_size_in_bytes?)))
+                .collect();
+            for resolved in &mut manifest_list_resolved {
+                if resolved.image.image_size_in_bytes.is_none() {
+                    resolved.image.image_size_in_bytes = resolved
+                        .manifests
+                        .iter()
+                        .map(|manifest| {
+                            let descriptor = manifest.descriptor.as_ref()?;
+                            sizes.get(descriptor.digest()).copied()
+                        })
+                        .sum();
+                }
+            }
             resolved_images.append(&mut manifest_list_resolved);
         }
+        for image in &mut resolved_images {
+            image.stats.registry_size_bytes = image.image.image_size_in_bytes;
+        }
+        if self.options.verify_digests {
+            let mismatches = self.digest_mismatches.lock().unwrap();
+            for image in &mut resolved_images {
+                image.digest_mismatches = [&image.image.manifest_digest]
+                    .into_iter()
+                    .chain(
+                        image
+                            .manifests
+                            .iter()
+                            .filter_map(|m| Some(m.descriptor.as_ref()?.digest())),
+                    )
+                    .filter(|digest| mismatches.contains(*digest))
+                    .cloned()
+                    .collect();
+            }
+        }
+        if self.options.fail_on_missing {
+            let failures = self.failures();
+            if !failures.is_empty() {
+                bail!(
+                    "BatchGetImage could not return {} manifests: {}",
+                    failures.len(),
+                    failures.iter().join(", ")
+                );
+            }
+        }
+        if self.options.fetch_config {
+            self.attach_image_configs(&mut resolved_images).await;
+        }
+        if self.options.scan_findings {
+            self.attach_scan_findings(&mut resolved_images)
+                .await
+                .context("Fetch scan findings")?;
+        }
 
         Ok(resolved_images)
     }
 
+    #[instrument(name = "configs", skip_all, fields(repo = %self))]
+    async fn attach_image_configs(&self, images: &mut [ImageWithManifests]) {
+        let digests: HashSet<String> = images
+            .iter()
+            .flat_map(|image| &image.manifests)
+            .map(|manifest| manifest.content.config())
+            .filter(|config| {
+                IMAGE_CONFIG_MEDIA_TYPES.contains(&config.media_type().to_string().as_str())
+            })
+            .map(|config| config.digest().to_string())
+            .collect();
+        let span = set_span_progress("", digests.len());
+        let configs: HashMap<String, ImageConfigSummary> = stream::iter(digests)
+            .map(|digest| async move {
+                match self.fetch_image_config(&digest).await {
+                    Ok(config) => Some((digest, config)),
+                    Err(e) => {
+                        warn!("Not recording config blob {digest} of {self}: {e:#}");
+                        self.options.metrics.add_errors(1);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .inspect(|_| span.pb_inc(1))
+            .filter_map(future::ready)
+            .collect()
+            .await;
+        for manifest in images.iter_mut().flat_map(|image| &mut image.manifests) {
+            manifest.image_config = configs.get(manifest.content.config().digest()).cloned();
+            if let (None, Some(config)) = (&manifest.os, &manifest.image_config) {
+                manifest.os = Some(config.os.clone());
+                manifest.architecture = Some(config.architecture.clone());
+                manifest.variant.clone_from(&config.variant);
+            }
+        }
+    }
+
+    #[instrument(name = "scans", skip_all, fields(repo = %self))]
+    async fn attach_scan_findings(&self, images: &mut [ImageWithManifests]) -> anyhow::Result<()> {
+        let span = set_span_progress("", images.len());
+        let scans: HashMap<String, ScanSummary> = stream::iter(images.iter())
+            .map(|image| async move {
+                let digest = &image.image.manifest_digest;
+                let scan = self
+                    .fetch_scan_findings(digest)
+                    .await
+                    .with_context(|| format!("Error fetching scan findings for {digest}"))?;
+                anyhow::Ok((digest.clone(), scan))
+            })
+            .buffer_unordered(self.concurrency)
+            .inspect(|_| span.pb_inc(1))
+            .try_collect()
+            .await?;
+        for image in images {
+            image.scan_findings = scans.get(&image.image.manifest_digest).cloned();
+        }
+        Ok(())
+    }
+
+    /// Only the severity counts are kept, so a single finding is requested.
+    async fn fetch_scan_findings(&self, digest: &str) -> anyhow::Result<ScanSummary> {
+        let input = DescribeImageScanFindingsInput::builder()
+            .set_registry_id(self.options.registry_id.clone())
+            .repository_name(self.repo_name.clone())
+            .image_id(ImageIdentifier::builder().image_digest(digest).build())
+            .max_results(1)
+            .build()?;
+        let result = self
+            .options
+            .retry
+            .retry("DescribeImageScanFindings", || async {
+                self.start_call(&self.api_calls.describe_image_scan_findings)
+                    .await;
+                self.client
+                    .describe_image_scan_findings(input.clone())
+                    .await
+            })
+            .await;
+        match result {
+            Ok(output) => Ok(output.into()),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_scan_not_found_exception()) =>
+            {
+                Ok(ScanSummary::not_scanned())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn fetch_image_config(&self, digest: &str) -> anyhow::Result<ImageConfigSummary> {
+        let blob = match &self.options.blob_cache {
+            Some(cache) => {
+                cache
+                    .get_or_fetch(digest, || self.download_blob(digest))
+                    .await?
+            }
+            None => self.download_blob(digest).await?,
+        };
+        let config: ImageConfiguration =
+            serde_json::from_slice(&blob).context("Parsing image config")?;
+        Ok(config.into())
+    }
+
+    async fn download_blob(&self, digest: &str) -> anyhow::Result<Vec<u8>> {
+        let input = GetDownloadUrlForLayerInput::builder()
+            .set_registry_id(self.options.registry_id.clone())
+            .repository_name(self.repo_name.clone())
+            .layer_digest(digest)
+            .build()?;
+        let response = self
+            .options
+            .retry
+            .retry("GetDownloadUrlForLayer", || async {
+                self.start_call(&self.api_calls.get_download_url_for_layer)
+                    .await;
+                self.client.get_download_url_for_layer(input.clone()).await
+            })
+            .await?;
+        let Some(url) = response.download_url else {
+            bail!("No download URL returned");
+        };
+        let body = self
+            .options
+            .retry
+            .retry_http("Blob download", || async {
+                http_client()
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await
+            })
+            .await?;
+        Ok(body.to_vec())
+    }
+
     #[instrument(name = "manifests", skip_all, fields(repo = %self))]
     pub async fn resolve_image_manifests<'a>(
         &'a self,
-        images_with_manifest_lists: Vec<(&'a RepositoryImage, Vec<Descriptor>)>,
+        images_with_manifest_lists: Vec<ManifestList<'a>>,
     ) -> anyhow::Result<Vec<ImageWithManifests>> {
-        let mut resolved_images = Vec::with_capacity(images_with_manifest_lists.len());
+        let max_depth = self.options.max_index_depth;
+        let mut parsed_manifests: HashMap<&RepositoryImage, Vec<_>> = HashMap::new();
+        // Every digest queued for an image, so an index that refers back to itself or an
+        // ancestor can't be followed forever.
+        let mut visited: HashMap<&RepositoryImage, HashSet<String>> = images_with_manifest_lists
+            .iter()
+            .map(|list| {
+                let digests = list
+                    .descriptors
+                    .iter()
+                    .map(|d| d.digest().to_string())
+                    .chain([list.image.manifest_digest.clone()])
+                    .collect();
+                (list.image, digests)
+            })
+            .collect();
+        let platforms = &self.options.platforms;
+        let mut pending = images_with_manifest_lists
+            .iter()
+            .map(|list| {
+                let children = list
+                    .descriptors
+                    .iter()
+                    .filter(|child| PlatformSpec::any_matches(platforms, child))
+                    .cloned()
+                    .collect_vec();
+                (list.image, children)
+            })
+            .collect_vec();
+        let span = set_span_progress("", 0);
 
-        let span = set_span_progress("", images_with_manifest_lists.len());
+        let mut depth = 1;
+        while !pending.is_empty() {
+            let mut nested = vec![];
+            for ((image, descriptor), digest, resolved_manifest) in
+                self.resolve_children(&pending, &span).await?
+            {
+                match ManifestType::from_media_type(&resolved_manifest.media_type) {
+                    ManifestType::Image => {
+                        parsed_manifests
+                            .entry(image)
+                            .or_default()
+                            .push(self.parse_image_manifest(&resolved_manifest, Some(descriptor))?);
+                    }
+                    ManifestType::List if depth >= max_depth => warn!(
+                        "Not resolving index {digest} of {image}: nested more than {max_depth} deep"
+                    ),
+                    ManifestType::List => {
+                        let index: ImageIndex = serde_json::from_str(&resolved_manifest.manifest)?;
+                        let seen = visited.entry(image).or_default();
+                        let children = index
+                            .manifests()
+                            .iter()
+                            .filter(|child| {
+                                let new = seen.insert(child.digest().to_string());
+                                if !new {
+                                    warn!(
+                                        "Not resolving {} of {image} again: index {digest} refers back to it",
+                                        child.digest()
+                                    );
+                                }
+                                new
+                            })
+                            .filter(|child| PlatformSpec::any_matches(platforms, child))
+                            .cloned()
+                            .collect_vec();
+                        nested.push((image, children));
+                    }
+                    ManifestType::SchemaV1 | ManifestType::Unknown => warn!(
+                        "Skipping child {digest} of {image} with unsupported media type {}",
+                        resolved_manifest.media_type
+                    ),
+                }
+            }
+            pending = nested;
+            depth += 1;
+        }
 
-        let all_results: Vec<_> = stream::iter(images_with_manifest_lists.iter())
+        Ok(images_with_manifest_lists
+            .iter()
+            .map(|list| {
+                let manifests = parsed_manifests.remove(list.image).unwrap_or_default();
+                let mut resolved = ImageWithManifests::new(list.image.clone(), manifests);
+                resolved.manifest_size = Some(list.manifest_size);
+                resolved.child_count = Some(list.descriptors.len());
+                resolved
+            })
+            .collect())
+    }
+
+    /// Fetch the manifest of every descriptor in `pending`, keyed by the image it belongs to.
+    async fn resolve_children<'a>(
+        &self,
+        pending: &[(&'a RepositoryImage, Vec<Descriptor>)],
+        span: &Span,
+    ) -> anyhow::Result<Vec<ResolvedChild<'a>>> {
+        // BatchGetImage takes at most `chunk_size` digests, so very large indexes are
+        // resolved over several calls.
+        let batches = pending
+            .iter()
+            .flat_map(|(image, descriptors)| {
+                descriptors
+                    .chunks(self.chunk_size)
+                    .map(move |chunk| (*image, chunk))
+            })
+            .collect_vec();
+        span.pb_inc_length(batches.len() as u64);
+        let results: Vec<_> = stream::iter(batches)
             .map(|(image, descriptors)| {
                 let manifest_digests_map: HashMap<_, _> = descriptors
                     .iter()
-                    .map(|d| (d.digest(), (*image, d)))
+                    .map(|d| (d.digest(), (image, d.clone())))
                     .collect();
                 async move {
                     self.batch_resolve_image_manifests(manifest_digests_map)
@@ -247,40 +1133,14 @@ impl ImageFetcher {
             .inspect(|_| span.pb_inc(1))
             .try_collect()
             .await?;
-
-        let grouping_map = all_results
-            .into_iter()
-            .flatten()
-            .into_group_map_by(|((img, _), _, _)| *img);
-        for (image, results) in grouping_map {
-            let mut parsed_manifests = vec![];
-            for ((_, descriptor), _, resolved_manifest) in results {
-                match ManifestType::from_str(&resolved_manifest.media_type) {
-                    Some(ManifestType::Image) => {
-                        let manifest: ImageManifest =
-                            serde_json::from_str(&resolved_manifest.manifest)?;
-                        parsed_manifests.push(ImageManifestWithDescriptor {
-                            content: manifest,
-                            descriptor: Some(descriptor.clone()),
-                        });
-                    }
-                    Some(_) => {
-                        bail!("Manifest list item resolved to another manifest list!")
-                    }
-                    None => {}
-                }
-            }
-            resolved_images.push(ImageWithManifests::new(image.clone(), parsed_manifests));
-        }
-
-        Ok(resolved_images)
+        Ok(results.into_iter().flatten().collect())
     }
 
     #[instrument(name = "descriptors", skip_all, fields(repo = %self))]
     pub async fn resolve_image_descriptors<'a>(
         &'a self,
         images: &'a [RepositoryImage],
-    ) -> anyhow::Result<ManifestsAndDescriptors> {
+    ) -> anyhow::Result<ManifestsAndDescriptors<'a>> {
         let mut resolved_images = vec![];
         let mut images_with_manifest_lists = vec![];
 
@@ -300,29 +1160,63 @@ impl ImageFetcher {
             .try_collect()
             .await?;
 
+        let mut returned_digests = HashSet::new();
         for results in all_results.into_iter() {
-            for (repo_image, _, resolved_manifest) in results {
-                match ManifestType::from_str(&resolved_manifest.media_type) {
-                    None => {}
-                    Some(ManifestType::Image) => {
-                        let manifest: ImageManifest =
-                            serde_json::from_str(&resolved_manifest.manifest)?;
-                        resolved_images.push(ImageWithManifests::new(
+            for (repo_image, digest, resolved_manifest) in results {
+                returned_digests.insert(digest);
+                match ManifestType::from_media_type(&resolved_manifest.media_type) {
+                    ManifestType::Unknown => {
+                        warn!(
+                            "Keeping {repo_image} without manifests: unsupported media type {}",
+                            resolved_manifest.media_type
+                        );
+                        let mut resolved = ImageWithManifests::new(repo_image.clone(), vec![]);
+                        resolved.image.manifest_type = ManifestType::Unknown;
+                        resolved
+                            .image
+                            .manifest_media_type
+                            .clone_from(&resolved_manifest.media_type);
+                        resolved.manifest_size = Some(resolved_manifest.manifest.len());
+                        resolved_images.push(resolved);
+                    }
+                    ManifestType::SchemaV1 => {
+                        let mut resolved = ImageWithManifests::new(repo_image.clone(), vec![]);
+                        resolved.image.manifest_type = ManifestType::SchemaV1;
+                        resolved.schema_v1 = SchemaV1Summary::parse(&resolved_manifest.manifest)
+                            .inspect_err(|e| {
+                                warn!("Can't parse schema 1 manifest of {repo_image}: {e}")
+                            })
+                            .ok();
+                        resolved.manifest_size = Some(resolved_manifest.manifest.len());
+                        resolved_images.push(resolved);
+                    }
+                    ManifestType::Image => {
+                        let mut resolved = ImageWithManifests::new(
                             repo_image.clone(),
-                            vec![ImageManifestWithDescriptor {
-                                content: manifest,
-                                descriptor: None,
-                            }],
-                        ))
+                            vec![self.parse_image_manifest(&resolved_manifest, None)?],
+                        );
+                        resolved.manifest_size = Some(resolved_manifest.manifest.len());
+                        resolved_images.push(resolved);
                     }
-                    Some(ManifestType::List) => {
+                    ManifestType::List => {
                         let parsed: ImageIndex = serde_json::from_str(&resolved_manifest.manifest)?;
-                        images_with_manifest_lists.push((repo_image, parsed.manifests().clone()));
+                        images_with_manifest_lists.push(ManifestList {
+                            image: repo_image,
+                            descriptors: parsed.manifests().clone(),
+                            manifest_size: resolved_manifest.manifest.len(),
+                        });
                     }
                 }
             }
         }
 
+        for image in images {
+            if !returned_digests.contains(&image.manifest_digest) {
+                warn!("BatchGetImage returned no manifest for {image}");
+                resolved_images.push(ImageWithManifests::missing(image.clone()));
+            }
+        }
+
         Ok((resolved_images, images_with_manifest_lists))
     }
 
@@ -340,32 +1234,73 @@ impl ImageFetcher {
             })
             .collect_vec();
         trace!("identifiers={identifiers:#?}");
-        let response = self
-            .client
-            .batch_get_image()
+        let input = BatchGetImageInput::builder()
+            .set_registry_id(self.options.registry_id.clone())
             .set_repository_name(Some(self.repo_name.clone()))
             .set_image_ids(Some(identifiers))
-            .send()
+            .build()?;
+        let response = self
+            .options
+            .retry
+            .retry("BatchGetImage", || async {
+                self.start_call(&self.api_calls.batch_get_image).await;
+                self.client.batch_get_image(input.clone()).await
+            })
             .await?;
-        let images = response.images.expect("No image output");
+        let failures = response
+            .failures()
+            .iter()
+            .map(|failure| ImageFailure {
+                digest: failure
+                    .image_id()
+                    .and_then(|id| id.image_digest())
+                    .map(str::to_string),
+                code: failure
+                    .failure_code()
+                    .map_or("UnknownFailure", |code| code.as_str())
+                    .to_string(),
+                reason: failure.failure_reason().map(str::to_string),
+            })
+            .collect_vec();
+        for failure in &failures {
+            warn!("BatchGetImage could not get a manifest from {self}: {failure}");
+        }
+        self.options.metrics.add_errors(failures.len() as u64);
+        self.failures.lock().unwrap().extend(failures);
+        let images = response.images.unwrap_or_default();
 
         trace!("digests={digests:#?}");
         trace!("images={images:#?}");
 
         let unique_images: Vec<_> = images
             .into_iter()
-            .map(|img| {
-                (
-                    img.image_id.unwrap().image_digest.unwrap(),
-                    img.image_manifest.unwrap(),
-                    img.image_manifest_media_type.unwrap(),
-                )
+            .filter_map(|img| {
+                Some((
+                    img.image_id?.image_digest?,
+                    img.image_manifest?,
+                    img.image_manifest_media_type?,
+                ))
             })
             .unique()
             .collect();
 
         let mut results = vec![];
         for (digest, manifest, media_type) in unique_images {
+            if self.options.verify_digests {
+                if let Some(expected) = digest.strip_prefix("sha256:") {
+                    let actual = format!("{:x}", Sha256::digest(manifest.as_bytes()));
+                    if actual != expected {
+                        warn!("Manifest {digest} in {self} hashes to sha256:{actual}");
+                        self.digest_mismatches
+                            .lock()
+                            .unwrap()
+                            .insert(digest.clone());
+                    }
+                }
+            }
+            if let Some(dir) = &self.options.raw_manifest_dir {
+                self.write_raw_manifest(dir, &digest, &manifest).await?;
+            }
             let d_value = digests
                 .get(&digest)
                 .with_context(|| format!("Digest {digest} not present in map"))?;
@@ -381,4 +1316,301 @@ impl ImageFetcher {
         }
         Ok(results)
     }
+
+    async fn write_raw_manifest(
+        &self,
+        dir: &Path,
+        digest: &str,
+        manifest: &str,
+    ) -> anyhow::Result<()> {
+        let repo_dir = dir.join(&self.repo_name);
+        tokio::fs::create_dir_all(&repo_dir)
+            .await
+            .with_context(|| format!("Creating {}", repo_dir.display()))?;
+        let file_name = digest.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        let path = repo_dir.join(format!("{file_name}.json"));
+        tokio::fs::write(&path, manifest)
+            .await
+            .with_context(|| format!("Writing {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_ecr::{
+        image_manifest, index_manifest, FakeEcr, IMAGE_MEDIA_TYPE, INDEX_MEDIA_TYPE,
+    };
+
+    /// Push an index of `children` new untagged images, returning the index's digest.
+    fn push_index(ecr: &FakeEcr, children: usize, tags: &[&str]) -> String {
+        let children = (0..children)
+            .map(|i| {
+                let manifest = image_manifest(&format!("child {i}"));
+                let digest = ecr.push("app", IMAGE_MEDIA_TYPE, &manifest, &[]);
+                (digest, manifest.len())
+            })
+            .collect_vec();
+        ecr.push("app", INDEX_MEDIA_TYPE, &index_manifest(&children), tags)
+    }
+
+    fn image_detail(pushed_at: i64, pulled_at: i64) -> ImageDetail {
+        ImageDetail::builder()
+            .repository_name("app")
+            .image_digest("sha256:abc")
+            .image_manifest_media_type(IMAGE_MEDIA_TYPE)
+            .image_pushed_at(aws_sdk_ecr::primitives::DateTime::from_secs(pushed_at))
+            .last_recorded_pull_time(aws_sdk_ecr::primitives::DateTime::from_secs(pulled_at))
+            .build()
+    }
+
+    #[test]
+    fn skips_image_with_out_of_range_push_time() {
+        assert!(RepositoryImage::from_image_detail(image_detail(i64::MAX, 0)).is_none());
+        assert!(RepositoryImage::from_image_detail(image_detail(i64::MIN, 0)).is_none());
+    }
+
+    #[test]
+    fn drops_out_of_range_pull_time() {
+        let image = RepositoryImage::from_image_detail(image_detail(1_700_000_000, i64::MAX))
+            .expect("image with a valid push time");
+        assert_eq!(image.image_pushed_at.timestamp(), 1_700_000_000);
+        assert_eq!(image.last_recorded_pull_time, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lists_and_resolves_every_page() {
+        let ecr = FakeEcr::default();
+        let digests = (0..5)
+            .map(|i| {
+                ecr.push(
+                    "app",
+                    IMAGE_MEDIA_TYPE,
+                    &image_manifest(&i.to_string()),
+                    &[],
+                )
+            })
+            .collect_vec();
+        ecr.throttle("DescribeImages", None);
+        let fetcher = ImageFetcher::new_with_config(ecr.clone(), "app".to_string(), 2, 100, 10);
+
+        let images = fetcher.fetch_images().await.unwrap();
+        assert_eq!(
+            images
+                .iter()
+                .map(|image| &image.manifest_digest)
+                .collect_vec(),
+            digests.iter().collect_vec()
+        );
+        let resolved = fetcher.resolve_images(&images).await.unwrap();
+        assert_eq!(resolved.len(), 5);
+        assert!(resolved.iter().all(
+            |image| image.resolution_status == ResolutionStatus::Resolved
+                && image.manifests.len() == 1
+        ));
+        // One throttled call, then three pages.
+        assert_eq!(ecr.calls("DescribeImages"), 4);
+        assert_eq!(fetcher.api_calls().describe_images, 4);
+        assert_eq!(ecr.batch_sizes(), [5]);
+    }
+
+    #[tokio::test]
+    async fn skips_excluded_digests_before_resolving() {
+        let ecr = FakeEcr::default();
+        let digests = (0..3)
+            .map(|i| {
+                ecr.push(
+                    "app",
+                    IMAGE_MEDIA_TYPE,
+                    &image_manifest(&i.to_string()),
+                    &[],
+                )
+            })
+            .collect_vec();
+        let mut filter = ImageFilter::default();
+        filter.excluded_digests.insert("app", &digests[0]);
+        filter.excluded_digests.insert("other", &digests[1]);
+        let mut fetcher = ImageFetcher::new(ecr.clone(), "app".to_string());
+        fetcher.options.filter = Arc::new(filter);
+
+        let images = fetcher.fetch_images().await.unwrap();
+        let resolved = fetcher.resolve_images(&images).await.unwrap();
+        assert_eq!(
+            resolved
+                .iter()
+                .map(|image| &image.image.manifest_digest)
+                .sorted()
+                .collect_vec(),
+            digests[1..].iter().sorted().collect_vec()
+        );
+        assert_eq!(ecr.batch_sizes(), [2]);
+    }
+
+    #[tokio::test]
+    async fn resolves_index_children() {
+        let ecr = FakeEcr::default();
+        let index = push_index(&ecr, 2, &["latest"]);
+        let fetcher = ImageFetcher::new(ecr.clone(), "app".to_string());
+
+        let images = fetcher.fetch_images().await.unwrap();
+        let resolved = fetcher.resolve_images(&images).await.unwrap();
+        let list = resolved
+            .iter()
+            .find(|image| image.image.manifest_digest == index)
+            .unwrap();
+        assert_eq!(list.image.manifest_type, ManifestType::List);
+        assert_eq!(list.child_count, Some(2));
+        // ECR reports no size for the index, so it gets its children's.
+        let children_size = images
+            .iter()
+            .filter(|image| image.manifest_type == ManifestType::Image)
+            .filter_map(|image| image.image_size_in_bytes)
+            .sum::<i64>();
+        assert_eq!(list.image.image_size_in_bytes, Some(children_size));
+        assert_eq!(list.stats.registry_size_bytes, Some(children_size));
+        let stats = serde_json::to_value(&list.stats).unwrap();
+        assert_eq!(stats["registry_size_bytes"], children_size);
+        assert_eq!(stats["manifest_sum_size_bytes"], list.stats.total_size);
+        assert_eq!(list.manifests.len(), 2);
+        assert!(list
+            .manifests
+            .iter()
+            .all(|manifest| manifest.architecture.as_deref() == Some("amd64")));
+    }
+
+    #[tokio::test]
+    async fn resolves_large_index_in_batches() {
+        let ecr = FakeEcr::default();
+        let index = push_index(&ecr, 150, &["latest"]);
+        let fetcher = ImageFetcher::new(ecr.clone(), "app".to_string());
+
+        let images = fetcher.fetch_images().await.unwrap();
+        let images = images
+            .into_iter()
+            .filter(|image| image.manifest_digest == index)
+            .collect_vec();
+        let resolved = fetcher.resolve_images(&images).await.unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].child_count, Some(150));
+        assert_eq!(resolved[0].manifests.len(), 150);
+        assert!(fetcher.failures().is_empty());
+
+        // The index itself, then its children over two calls.
+        let mut batches = ecr.batch_sizes();
+        assert_eq!(batches.remove(0), 1);
+        batches.sort();
+        assert_eq!(batches, [50, 100]);
+    }
+
+    #[tokio::test]
+    async fn continuing_on_error_still_fails_deleted_repository() {
+        let mut fetcher = ImageFetcher::new(FakeEcr::default(), "gone".to_string());
+        fetcher.options.continue_on_error = true;
+
+        let e = fetcher.fetch_images().await.unwrap_err();
+        assert!(crate::ecr_api::is_repository_not_found(&e));
+        assert!(!fetcher.is_partial());
+    }
+
+    #[tokio::test]
+    async fn only_fetches_image_configs() {
+        let ecr = FakeEcr::default();
+        ecr.push("app", IMAGE_MEDIA_TYPE, &image_manifest("image"), &["v1"]);
+        let chart = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": IMAGE_MEDIA_TYPE,
+            "config": {
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2,
+            },
+            "layers": [],
+        });
+        ecr.push("app", IMAGE_MEDIA_TYPE, &chart.to_string(), &["chart"]);
+        let mut fetcher = ImageFetcher::new(ecr.clone(), "app".to_string());
+        fetcher.options.fetch_config = true;
+
+        // The image's config can't be downloaded, which leaves it unset.
+        let images = fetcher.fetch_images().await.unwrap();
+        let resolved = fetcher.resolve_images(&images).await.unwrap();
+        assert_eq!(resolved.len(), 2);
+        assert!(resolved
+            .iter()
+            .all(|image| image.manifests[0].image_config.is_none()));
+        assert_eq!(ecr.calls("GetDownloadUrlForLayer"), 1);
+        assert_eq!(fetcher.options.metrics.snapshot().errors, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_config_downloads() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let config = r#"{"architecture":"arm64","os":"linux","rootfs":{"type":"layers","diff_ids":[]},"history":[]}"#;
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut buf = [0; 1024];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let body = if status == "200 OK" { config } else { "" };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let ecr = FakeEcr::default();
+        ecr.serve_blobs_from(&format!("http://{address}"));
+        ecr.push("app", IMAGE_MEDIA_TYPE, &image_manifest("image"), &["v1"]);
+        let mut fetcher = ImageFetcher::new(ecr, "app".to_string());
+        fetcher.options.fetch_config = true;
+        fetcher.options.retry.base_delay = std::time::Duration::from_millis(1);
+
+        let images = fetcher.fetch_images().await.unwrap();
+        let resolved = fetcher.resolve_images(&images).await.unwrap();
+        server.await.unwrap();
+        let manifest = &resolved[0].manifests[0];
+        assert_eq!(
+            manifest.image_config.as_ref().unwrap().architecture,
+            "arm64"
+        );
+        assert_eq!(manifest.architecture.as_deref(), Some("arm64"));
+    }
+
+    #[tokio::test]
+    async fn merges_tagged_child_of_tagged_index() {
+        let ecr = FakeEcr::default();
+        let child_manifest = image_manifest("amd64");
+        let child = ecr.push("app", IMAGE_MEDIA_TYPE, &child_manifest, &["v1-amd64"]);
+        let index = ecr.push(
+            "app",
+            INDEX_MEDIA_TYPE,
+            &index_manifest(&[(child.clone(), child_manifest.len())]),
+            &["v1"],
+        );
+        let fetcher = ImageFetcher::new(ecr, "app".to_string());
+
+        let images = fetcher.fetch_images().await.unwrap();
+        let resolved = fetcher.resolve_images(&images).await.unwrap();
+        assert_eq!(resolved.len(), 2);
+
+        let merged = merge_standalone_children(resolved);
+        assert_eq!(merged.len(), 1);
+        let list = &merged[0];
+        assert_eq!(list.image.manifest_digest, index);
+        assert_eq!(list.image.image_tags, ["v1"]);
+        assert_eq!(list.manifests.len(), 1);
+        let manifest = &list.manifests[0];
+        assert_eq!(manifest.descriptor.as_ref().unwrap().digest(), &child);
+        assert_eq!(
+            manifest.standalone_tags.as_deref(),
+            Some(&["v1-amd64".to_string()][..])
+        );
+    }
 }
@@ -0,0 +1,65 @@
+use std::pin::pin;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// Exit status after a dump is stopped with Ctrl-C, as for a shell killed by SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+/// Why the dump was stopped early.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Interrupted,
+    TimedOut,
+}
+
+static STOPPED: OnceLock<Stop> = OnceLock::new();
+static NOTIFY: Notify = Notify::const_new();
+
+fn stop(reason: Stop) {
+    if STOPPED.set(reason).is_ok() {
+        NOTIFY.notify_waiters();
+    }
+}
+
+/// Catch Ctrl-C so the dump can stop between repositories and finish its outputs. A
+/// second Ctrl-C exits immediately.
+pub fn install() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        warn!("Interrupted: finishing the repository being written, Ctrl-C again to exit now");
+        stop(Stop::Interrupted);
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(EXIT_CODE);
+        }
+    });
+}
+
+/// Stop the dump the same way once `timeout` has passed since `started`.
+pub fn stop_after(timeout: Duration, started: Instant) {
+    tokio::spawn(async move {
+        tokio::time::sleep_until((started + timeout).into()).await;
+        warn!(
+            "Timed out after {}: finishing the repository being written",
+            humantime::format_duration(timeout)
+        );
+        stop(Stop::TimedOut);
+    });
+}
+
+pub fn stopped() -> Option<Stop> {
+    STOPPED.get().copied()
+}
+
+/// Resolves once the dump has been stopped.
+pub async fn interrupted() {
+    let mut notified = pin!(NOTIFY.notified());
+    notified.as_mut().enable();
+    if stopped().is_some() {
+        return;
+    }
+    notified.await;
+}
@@ -0,0 +1,54 @@
+use ecr_dump::images::ImageWithManifests;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Every layer referenced by the dump, to compare unique layer storage with the sum over
+/// images.
+#[derive(Debug, Default)]
+pub struct LayerStats {
+    layers: HashMap<String, u64>,
+    references: u64,
+    referenced_bytes: u64,
+}
+
+/// The record written at the end of a dump with `--layer-stats`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LayerStatsRecord {
+    pub layer_stats: LayerStatsSummary,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LayerStatsSummary {
+    pub unique_layers: usize,
+    pub unique_bytes: u64,
+    /// Layers counted once per manifest referencing them.
+    pub referenced_layers: u64,
+    pub referenced_bytes: u64,
+    /// `referenced_bytes / unique_bytes`, or 1 for an empty dump.
+    pub dedup_ratio: f64,
+}
+
+impl LayerStats {
+    pub fn add(&mut self, image: &ImageWithManifests) {
+        for layer in image.manifests.iter().flat_map(|m| m.content.layers()) {
+            let size = layer.size().max(0) as u64;
+            self.references += 1;
+            self.referenced_bytes += size;
+            self.layers.insert(layer.digest().to_string(), size);
+        }
+    }
+
+    pub fn summary(&self) -> LayerStatsSummary {
+        let unique_bytes = self.layers.values().sum();
+        LayerStatsSummary {
+            unique_layers: self.layers.len(),
+            unique_bytes,
+            referenced_layers: self.references,
+            referenced_bytes: self.referenced_bytes,
+            dedup_ratio: match unique_bytes {
+                0 => 1.0,
+                unique_bytes => self.referenced_bytes as f64 / unique_bytes as f64,
+            },
+        }
+    }
+}
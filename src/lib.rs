@@ -0,0 +1,46 @@
+//! Library interface to `ecr-dump`.
+//!
+//! Every entry point takes an [`aws_sdk_ecr::Client`] that you construct yourself, so
+//! region, credentials and interceptors all come from your client. Throttled calls,
+//! server errors and connection failures are retried by the crate's [`RetryPolicy`],
+//! which counts every attempt in the API call totals, so build the client with
+//! `RetryConfig::disabled()` or the SDK will retry each of those attempts as well.
+//! The `env-config` feature (enabled by default) adds [`Dumper::from_env`] as a
+//! convenience for building one from the standard AWS environment instead.
+//!
+//! [`Dumper`] is the main entry point: list repositories with
+//! [`Dumper::list_repositories`], then resolve them with [`Dumper::fetch_repo`] or
+//! stream their images through [`Dumper::dump_for_each_concurrent`]. The lower level
+//! [`ImageFetcher`] and [`RepositoryLister`] are available for finer control over a
+//! single repository or listing. Both are generic over [`EcrApi`], which can be
+//! implemented over in-memory fixtures to run them without AWS.
+//!
+//! The types re-exported at the crate root follow semver. The serialized form of
+//! [`ImageWithManifests`] only gains fields within a minor version. Other items in the
+//! public modules are exposed for advanced use and may change between minor versions.
+
+pub mod blob_cache;
+mod dumper;
+pub mod ecr_api;
+#[cfg(test)]
+mod fake_ecr;
+pub mod filter_expr;
+pub mod filters;
+pub mod images;
+pub mod metrics;
+pub mod progress;
+pub mod pull_metrics;
+pub mod rate_limit;
+pub mod regions;
+pub mod repos;
+pub mod retry;
+pub mod scan_findings;
+pub mod signing;
+pub mod validation;
+
+pub use dumper::{Dumper, FetchedRepository};
+pub use ecr_api::EcrApi;
+pub use filters::ImageFilter;
+pub use images::{FetchOptions, ImageFetcher, ImageWithManifests, RepositoryImage};
+pub use repos::{RepositoryLister, RepositoryName};
+pub use retry::RetryPolicy;
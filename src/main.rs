@@ -1,133 +1,1768 @@
-mod images;
-mod progress;
-mod repos;
+mod baseline;
+mod check_permissions;
+mod checkpoint;
+mod concurrency;
+mod config_file;
+mod csv_rows;
+mod fields;
+mod global_sort;
+mod interrupt;
+mod layer_stats;
+mod oci_refs;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod output;
+mod prometheus;
+mod s3_upload;
+mod summary;
+mod tag_map;
+mod validate_dump;
 
-use crate::images::{ImageFetcher, ImageWithManifests};
-use crate::repos::{RepositoryLister, RepositoryName};
-use anyhow::Context;
+use crate::baseline::Baseline;
+use crate::checkpoint::Checkpoint;
+use crate::concurrency::Concurrency;
+use crate::fields::Field;
+use crate::global_sort::GlobalSorter;
+use crate::interrupt::Stop;
+use crate::layer_stats::{LayerStats, LayerStatsRecord};
+use crate::oci_refs::OciReference;
+use crate::output::{OutputCompression, OutputFormat, OutputWriter, RecordWriter, SinkFailure};
+use crate::s3_upload::s3_location;
+use crate::summary::RunSummary;
+use crate::tag_map::{TagMap, UntaggedImages};
+use anyhow::{bail, Context};
+use aws_config::retry::RetryConfig;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_sdk_ecr::config::{ProvideCredentials, SharedCredentialsProvider};
+use aws_sdk_ecr::types::{Repository, TagStatus};
 use aws_sdk_ecr::Client;
-use clap::Parser;
-use futures_util::stream::{self as stream, StreamExt};
-use globset::{Glob, GlobSet};
-use std::path::PathBuf;
-use tokio::io::AsyncWriteExt;
-use tracing::{debug, info, instrument, Level};
+use chrono::{DateTime, Utc};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use ecr_dump::blob_cache::BlobCache;
+use ecr_dump::filter_expr::FilterExpr;
+use ecr_dump::filters::{DigestSet, ImageFilter, PlatformSpec};
+use ecr_dump::images::{ImageWithManifests, LayerCompression};
+use ecr_dump::progress;
+use ecr_dump::pull_metrics::PullMetricsFetcher;
+use ecr_dump::rate_limit::RateLimiter;
+use ecr_dump::regions::ECR_REGIONS;
+use ecr_dump::repos::{
+    RepositoryEncryption, RepositoryLister, RepositoryMetadata, RepositoryName, RepositoryRecord,
+    RepositorySummary,
+};
+use ecr_dump::retry::{Jitter, RetryPolicy};
+use ecr_dump::{Dumper, FetchOptions, FetchedRepository};
+use futures_util::stream::{self as stream, StreamExt, TryStreamExt};
+use globset::{Glob, GlobBuilder, GlobSet};
+use itertools::Itertools;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::IsTerminal;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWrite;
+use tracing::{debug, info, instrument, warn, Level};
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 use tracing_indicatif::IndicatifLayer;
 use tracing_subscriber::filter::Directive;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::Registry;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{EnvFilter, Layer};
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    output: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
+    /// Files to write JSONL records to, `-` for stdout, `unix:/path/to.sock` to stream
+    /// them into a listening Unix domain socket, or `s3://bucket/key` to stream them into
+    /// S3 with a multipart upload. Every record is written to each output, and files and
+    /// S3 objects ending `.gz` or `.zst` are compressed to match unless --compress is given.
+    #[arg(required_unless_present = "dry_run", num_args = 1..)]
+    outputs: Vec<PathBuf>,
+
+    /// TOML file of defaults for any of these options, keyed by option name, with
+    /// `outputs` for the outputs. Options given on the command line take precedence.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// With several outputs, whether a failed write to one of them stops the dump or
+    /// just that output.
+    #[arg(long, value_enum, default_value_t = SinkFailure::Abort)]
+    on_output_failure: SinkFailure,
+
+    /// Compress file outputs, appending `.gz` or `.zst` to their names if missing. By
+    /// default, compression is inferred from each output's extension.
+    #[arg(long, value_enum, conflicts_with = "framed_gzip")]
+    compress: Option<OutputCompression>,
+
+    /// Compression level for gzip (1-9) or zstd (1-22). Defaults to each codec's default.
+    #[arg(long)]
+    compress_level: Option<i32>,
+
+    /// Repositories and requests processed at once, or `auto` to size from the CPU count.
+    /// Up to `repositories * requests` ECR requests can be in flight at once.
     #[arg(short, long, default_value = "10")]
-    concurrency: usize,
+    concurrency: Concurrency,
+
+    /// Repositories processed at once, overriding `--concurrency`.
+    #[arg(long)]
+    repo_concurrency: Option<NonZeroUsize>,
+
+    /// Requests in flight per repository while resolving its images, overriding
+    /// `--concurrency`.
+    #[arg(long)]
+    image_concurrency: Option<NonZeroUsize>,
+
+    /// Start at most this many ECR image requests per second across the whole run.
+    /// The concurrency flags bound how many requests are in flight at once, so the effective
+    /// rate is whichever limit is reached first: with slow responses, raising
+    /// concurrency is what gets closer to this rate.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_requests_per_second: Option<u32>,
+
+    /// Images listed per `DescribeImages` call. ECR allows at most 1000.
+    #[arg(long, default_value = "1000", value_parser = clap::value_parser!(i32).range(1..=1000))]
+    page_size: i32,
+
+    /// Manifests fetched per `BatchGetImage` call. ECR allows at most 100.
+    #[arg(long, default_value = "100", value_parser = clap::value_parser!(u8).range(1..=100))]
+    batch_size: u8,
+
+    /// Only dump repositories matching one of these globs.
+    #[arg(long)]
+    include: Option<Vec<String>>,
+
+    /// Skip repositories matching any of these globs, even if they match --include.
+    #[arg(long)]
+    exclude: Option<Vec<String>>,
+
+    /// File of `--include` globs, one per line. Blank lines and lines starting with `#`
+    /// are skipped. Combined with any `--include` globs.
+    #[arg(long)]
+    include_file: Option<PathBuf>,
+
+    /// File of `--exclude` globs, in the same format as `--include-file`.
+    #[arg(long)]
+    exclude_file: Option<PathBuf>,
+
+    /// Also dump repositories with a name containing a match for this regex. Anchor it
+    /// with `^` and `$` to match whole names. A repository matching either this or an
+    /// `--include` glob is included.
+    #[arg(long)]
+    include_regex: Option<Regex>,
+
+    /// Skip repositories with a name containing a match for this regex. Exclusions, glob
+    /// or regex, always win over inclusions.
+    #[arg(long)]
+    exclude_regex: Option<Regex>,
+
+    /// Only dump images with a tag matching one of these globs. Untagged images are
+    /// skipped.
+    #[arg(long)]
+    tag_include: Option<Vec<String>>,
+
+    /// Skip images with any tag matching one of these globs, even if another tag matches
+    /// --tag-include.
+    #[arg(long)]
+    tag_exclude: Option<Vec<String>>,
+
+    /// Match the repository and tag globs, and those in `--include-file` and
+    /// `--exclude-file`, ignoring case.
+    #[arg(long)]
+    case_insensitive: bool,
+
+    /// Only dump images pushed at or after this RFC3339 time, e.g. `2024-01-01T00:00:00Z`.
+    #[arg(long, value_parser = parse_rfc3339)]
+    pushed_after: Option<DateTime<Utc>>,
+
+    /// Only dump images pushed strictly before this RFC3339 time.
+    #[arg(long, value_parser = parse_rfc3339)]
+    pushed_before: Option<DateTime<Utc>>,
+
+    /// Only dump images not pulled since this time: either an age such as `90d`, or a
+    /// `YYYY-MM-DD` date or RFC3339 time. Each image record gets `days_since_pull`.
+    #[arg(long, value_parser = parse_stale_since)]
+    stale_since: Option<DateTime<Utc>>,
+
+    /// With --stale-since, also dump images that have no recorded pull at all.
+    #[arg(long, requires = "stale_since")]
+    include_never_pulled: bool,
+
+    /// Only dump images with at least one tag.
+    #[arg(long, conflicts_with = "untagged_only")]
+    tagged_only: bool,
+
+    /// Only dump images without tags, such as candidates for cleanup.
+    #[arg(long)]
+    untagged_only: bool,
+
+    /// Advance the progress bar per repository, or per image. Counting images
+    /// requires an extra `DescribeImages` pass before resolution starts, which is
+    /// skipped when progress bars are off.
+    #[arg(long, value_enum, default_value_t = ProgressBy::Repos)]
+    progress_by: ProgressBy,
+
+    /// Log plainly without progress bars. Implied when stderr isn't a terminal.
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Only log warnings and errors, and don't print the summary. `RUST_LOG` still takes
+    /// precedence when set.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Log more: `-v` for debug logs and `-vv` for trace logs. `RUST_LOG` still takes
+    /// precedence when set. Release builds only include info logs and above.
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Format of the logs written to stderr. `json` writes one object per event, with
+    /// the fields of its spans.
+    #[arg(long, value_enum, default_value_t = LogFormat::Compact)]
+    log_format: LogFormat,
+
+    /// Export tracing spans, such as each repository's fetch, to this OTLP/HTTP traces
+    /// endpoint, like `http://localhost:4318/v1/traces`. Spans are filtered by level like
+    /// logs, so `--quiet` exports none.
+    #[cfg(feature = "otlp")]
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Emit a rollup record per repository with its CloudWatch `RepositoryPullCount` total.
+    #[arg(long)]
+    pull_metrics: bool,
+
+    /// Number of days of pull metrics to sum, from 1 to 455, the retention of CloudWatch's
+    /// hourly datapoints.
+    #[arg(
+        long,
+        default_value = "30",
+        requires = "pull_metrics",
+        value_parser = clap::value_parser!(u32).range(1..=455)
+    )]
+    pull_metrics_days: u32,
+
+    /// Check manifests against the OCI schema and record any unexpected or missing
+    /// fields on each manifest as `validation_issues`.
+    #[arg(long)]
+    strict_manifests: bool,
+
+    /// Download each manifest's config blob and record its architecture, OS and creation
+    /// date as `image_config`. Adds a `GetDownloadUrlForLayer` call and a download per
+    /// distinct config. Artifacts such as Helm charts are skipped, and a config that
+    /// can't be downloaded is left out with a warning.
+    #[arg(long, global = true)]
+    fetch_config: bool,
+
+    /// Only resolve the platform manifests of manifest lists for this platform, such as
+    /// `linux/amd64` or `linux/arm64/v8`. Repeat to keep several. Children without a
+    /// platform count as `unknown/unknown`, which is what BuildKit attestations use, so
+    /// pass `--platform unknown/unknown` to keep them too.
+    #[arg(long = "platform", value_name = "OS/ARCH[/VARIANT]")]
+    platforms: Vec<PlatformSpec>,
+
+    /// Record each image's latest vulnerability scan as `scan_findings`: its status,
+    /// completion time and finding counts by severity. Images that were never scanned
+    /// get the status `not_scanned`. Adds a `DescribeImageScanFindings` call per image.
+    #[arg(long, global = true)]
+    include_scan_findings: bool,
+
+    /// Check that each manifest ECR returns hashes to its `sha256` digest. Mismatches are
+    /// logged and listed in the image's `digest_mismatches`.
+    #[arg(long)]
+    verify_digests: bool,
+
+    /// Record each image manifest exactly as ECR returned it, as a `raw_manifest` string,
+    /// such as for verifying signatures over the original bytes.
+    #[arg(long)]
+    include_raw_manifest: bool,
+
+    /// How many levels of nested manifest lists to follow. Lists nested deeper are
+    /// skipped with a warning.
+    #[arg(long, default_value = "3")]
+    max_index_depth: usize,
+
+    /// Cache downloaded config blobs in this directory, so repeated dumps don't fetch
+    /// them again.
+    #[arg(long, requires = "fetch_config")]
+    blob_cache_dir: Option<PathBuf>,
+
+    /// Evict the oldest cached blobs once the cache grows past this many bytes.
+    #[arg(long, requires = "blob_cache_dir")]
+    blob_cache_max_bytes: Option<u64>,
+
+    /// Dump every region ECR is available in, stamping the region onto each record.
+    /// Regions that can't be listed (not enabled, no access) are skipped with a warning.
+    #[arg(long, conflicts_with = "region")]
+    all_regions: bool,
+
+    /// AWS region to use, instead of the one from the environment or profile.
+    #[arg(long, global = true)]
+    region: Option<String>,
+
+    /// Named profile from `~/.aws/config` and `~/.aws/credentials` to take credentials and
+    /// region from. Takes precedence over `AWS_PROFILE`.
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Assume this IAM role, using the environment or profile credentials, before calling
+    /// ECR. The role's credentials are refreshed as they expire.
+    #[arg(long, global = true)]
+    assume_role_arn: Option<String>,
+
+    /// Session name to use with --assume-role-arn.
+    #[arg(
+        long,
+        global = true,
+        default_value = "ecr-dump",
+        requires = "assume_role_arn"
+    )]
+    role_session_name: String,
+
+    /// Send AWS requests to this endpoint instead of the default, e.g. LocalStack or a VPC
+    /// interface endpoint.
+    #[arg(long, global = true)]
+    endpoint_url: Option<String>,
+
+    /// Account ID of the registry to dump, when reading another account's registry that
+    /// has granted this identity access. Defaults to the caller's own registry.
+    #[arg(long, global = true)]
+    registry_id: Option<String>,
+
+    /// Write JSON lines, a single JSON array of records, or CSV with one row per platform
+    /// manifest: repository, digest, manifest type, comma-joined tags, pushed at, last
+    /// pulled, platform digest and layer count. Repository records are not written as CSV.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Jsonl)]
+    output_format: OutputFormat,
 
+    /// Also write the end-of-run summary printed to stderr to this file, as JSON.
     #[arg(long)]
-    include: Option<Vec<Glob>>,
+    summary_file: Option<PathBuf>,
 
+    /// Write Prometheus text-format metrics about the run to this file when it finishes,
+    /// successfully or not, for node_exporter's textfile collector.
     #[arg(long)]
-    exclude: Option<Vec<Glob>>,
+    metrics_file: Option<PathBuf>,
+
+    /// Only write the given comma-separated fields of each image, e.g. `repo,digest,tags,size`.
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "oci_refs")]
+    fields: Option<Vec<Field>>,
+
+    /// Write each image as an OCI descriptor (`mediaType`, `digest`, `size`,
+    /// `annotations`) instead of the full record, for OCI-native tooling.
+    #[arg(long)]
+    oci_refs: bool,
+
+    /// Compress every record as an independent gzip member so any record can be
+    /// decompressed on its own, and write the `<offset> <length>` of each record to a
+    /// `<output>.idx` sidecar for random access. Applies to every output, in place of
+    /// compressing by extension.
+    #[arg(long)]
+    framed_gzip: bool,
+
+    /// Skip repositories holding fewer than this many images, before resolving them.
+    #[arg(long, default_value = "0")]
+    min_repo_images: usize,
+
+    /// Record each repository's encryption configuration (`AES256`, or `KMS` with the
+    /// key ARN) on its per-repository record.
+    #[arg(long)]
+    include_kms_encryption_context: bool,
+
+    /// Record each repository's ARN, creation time, tag mutability, scan-on-push setting
+    /// and encryption type as `metadata` on its per-repository record.
+    #[arg(long)]
+    repo_metadata: bool,
+
+    /// Record each repository's resource tags, such as team ownership or cost centre, as
+    /// `tags` on its per-repository record. Adds a `ListTagsForResource` call per
+    /// repository.
+    #[arg(long, global = true)]
+    repo_tags: bool,
+
+    /// Skip images whose digest is listed in this file: one `sha256:...` (any repository)
+    /// or `repo@sha256:...` per line. Applied after the repository filters, and before
+    /// manifests are resolved.
+    #[arg(long)]
+    exclude_digests_from: Option<PathBuf>,
+
+    /// Only dump images matching this expression, e.g.
+    /// `size > 1GB and not tagged and pushed_at < 2024-01-01`. Fields are repo, tag, type,
+    /// pushed_at, size, tag_count and tagged, combined with and/or/not and parentheses.
+    #[arg(long, value_parser = FilterExpr::parse)]
+    filter: Option<FilterExpr>,
+
+    /// Record how many of each repository's images have cosign signatures, attestations
+    /// and SBOMs (found by their `sha256-<hex>.sig`/`.att`/`.sbom` tags) on its
+    /// per-repository record.
+    #[arg(long)]
+    include_referrers: bool,
+
+    /// Also write every manifest exactly as returned by ECR to
+    /// `<dir>/<repo>/<digest>.json`, with `:` in the digest replaced by `_`.
+    #[arg(long)]
+    raw_manifest_dir: Option<PathBuf>,
+
+    /// Once the dump completes, write a `{repository: {tag: digest}}` JSON index of every
+    /// dumped image to this path. With --all-regions each region gets its own
+    /// `{region: {repository: {tag: digest}}}` entry.
+    #[arg(long)]
+    tag_map: Option<PathBuf>,
+
+    /// How the tag map represents images without tags.
+    #[arg(long, value_enum, default_value_t = UntaggedImages::Omit, requires = "tag_map")]
+    tag_map_untagged: UntaggedImages,
+
+    /// Only write images that aren't in this previous JSONL dump (optionally `.gz` or
+    /// `.zst`), to produce a delta. The baseline should come from a run with the same
+    /// filters.
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// With --baseline, also write images whose tags changed since the baseline.
+    #[arg(long, requires = "baseline")]
+    baseline_tag_changes: bool,
+
+    /// With --baseline, write the repository and digest of every baseline image that no
+    /// longer exists to this file as JSONL. Repositories that were skipped or only
+    /// partially listed are left out.
+    #[arg(long, requires = "baseline", conflicts_with = "resume")]
+    removed_file: Option<PathBuf>,
+
+    /// Write the name of every repository that had no images left after fetching and
+    /// filtering to this file, one per line. They are always logged when the dump ends.
+    #[arg(long)]
+    empty_repos_file: Option<PathBuf>,
+
+    /// How many times to retry an ECR or CloudWatch call that was throttled, failed with a
+    /// server error, or lost its connection or timed out. Other errors, such as access
+    /// denied, are never retried.
+    #[arg(long, default_value = "5")]
+    max_retries: u32,
+
+    /// Delay before the first retry of a throttled call, doubling on each further retry.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "200ms")]
+    retry_base_delay: Duration,
+
+    /// Longest delay between retries of a throttled call.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "20s")]
+    retry_max_delay: Duration,
+
+    /// Randomise retry delays: `full` (0 to the delay), `equal` (half to the full delay)
+    /// or `none`.
+    #[arg(long, default_value_t = Jitter::Full)]
+    retry_jitter: Jitter,
+
+    /// After each repository's records are flushed, append its name and the output
+    /// length so far to this file, so an interrupted dump can be picked up with --resume.
+    #[arg(long, conflicts_with = "global_sort")]
+    checkpoint_file: Option<PathBuf>,
+
+    /// Also checkpoint within a repository after every N images, so --resume doesn't fetch
+    /// the images of an interrupted repository already written, instead of starting it
+    /// again. Each checkpoint flushes the outputs and syncs the checkpoint file, and adds a
+    /// line holding the batch's digests, about 72 bytes per image.
+    #[arg(long, value_name = "N", requires = "checkpoint_file")]
+    checkpoint_images: Option<NonZeroUsize>,
+
+    /// Stop the dump once it has run this long, such as `2h`, then finish the outputs
+    /// and exit with an error. Repositories written by then are complete, and with
+    /// --checkpoint-file a later --resume carries on from the next one.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Give up on a repository whose images haven't been fetched within this long,
+    /// skipping it with a warning. Skipped repositories aren't checkpointed, so --resume
+    /// retries them.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    repo_timeout: Option<Duration>,
+
+    /// Skip repositories already recorded in --checkpoint-file and append to the
+    /// existing outputs, first cutting off anything written by the repository that was
+    /// interrupted. Outputs must be uncompressed. The summary and tag map only cover
+    /// this run's images.
+    #[arg(long, requires = "checkpoint_file")]
+    resume: bool,
+
+    /// Count the unique layers referenced across the dump and log their total size against
+    /// the sum over every manifest. Unless writing CSV, the totals are also written as a
+    /// final `{"layer_stats": ...}` record.
+    #[arg(long)]
+    layer_stats: bool,
+
+    /// Write the whole dump ordered by region (with --all-regions), repository name, then
+    /// manifest digest, rather than as each repository completes. Nothing is written until
+    /// every repository has been fetched.
+    #[arg(long)]
+    global_sort: bool,
+
+    /// With --global-sort, the number of records held in memory. Beyond this, sorted
+    /// batches are spilled to temporary files and merged at the end, trading memory for
+    /// temporary disk space roughly the size of the output.
+    #[arg(long, default_value_t = 500_000, requires = "global_sort")]
+    global_sort_buffer: usize,
+
+    /// Stamp each image record with `emitted_at`, the time it was written. Off by default
+    /// so repeated dumps of an unchanged registry stay byte-identical.
+    #[arg(long)]
+    emit_timestamps: bool,
+
+    /// If listing a repository's images fails partway, dump the images listed so far
+    /// instead of failing. The repository's record is then written with `partial: true`.
+    /// A repository deleted mid-dump is still skipped as without this flag.
+    #[arg(long)]
+    continue_on_error: bool,
+
+    /// Fail if `BatchGetImage` can't return a listed manifest, such as an image deleted
+    /// mid-dump. By default the failures are logged and recorded as `failures` on the
+    /// repository's record.
+    #[arg(long)]
+    fail_on_missing: bool,
+
+    /// Fail if a repository is deleted between being listed and being dumped. By default
+    /// it is skipped with a warning.
+    #[arg(long)]
+    fail_on_repo_errors: bool,
+
+    /// Don't sort repositories by name before dumping them. Repositories are then started
+    /// in whatever order ECR lists them, which can differ between runs. Every page of
+    /// repositories is still listed before the first one is dumped.
+    #[arg(long)]
+    no_sort_repos: bool,
+
+    /// Write each repository's images in the order they were resolved, instead of by
+    /// push time then digest with each image's manifests ordered by digest. The order
+    /// then differs between runs.
+    #[arg(long)]
+    no_sort: bool,
+
+    /// Don't write a separate record for an image that is also a child of a manifest list
+    /// in the same repository. Its tags are kept on the list's child manifest as
+    /// `standalone_tags`.
+    #[arg(long)]
+    dedupe_records: bool,
+
+    /// Also write every record to stdout, uncompressed, for piping into `jq` while the
+    /// dump is saved. Logs and progress stay on stderr.
+    #[arg(long)]
+    tee: bool,
+
+    /// List repositories and count the images a dump would resolve, printing the totals
+    /// and the minimum number of BatchGetImage calls, without resolving images or
+    /// writing any output.
+    #[arg(long, conflicts_with = "list_repos_only")]
+    dry_run: bool,
+
+    /// Only list the repositories a dump would cover, after --include and --exclude, and
+    /// write them as `{"repository_name": ...}` records (or a single-column CSV) without
+    /// fetching any images.
+    #[arg(
+        long,
+        conflicts_with_all = ["fields", "oci_refs", "checkpoint_file", "baseline", "split_by_repo"]
+    )]
+    list_repos_only: bool,
+
+    /// Treat the output as a directory and write each repository to its own
+    /// `<repo>.jsonl` (or `.csv`) file, with `/` in the name written as `%2F`. Every
+    /// dumped repository gets a file, left empty if it has no images; repositories
+    /// skipped by --min-repo-images get none. With --all-regions, each region's files go
+    /// in a subdirectory named after it.
+    #[arg(
+        long,
+        conflicts_with_all = ["global_sort", "checkpoint_file", "framed_gzip", "tee"]
+    )]
+    split_by_repo: bool,
+
+    /// Warn when a repository lists more than this many images, before they are resolved.
+    #[arg(long, value_name = "N")]
+    warn_large_repo: Option<usize>,
+
+    /// Record each image's repository URI (`<registry>.dkr.ecr.<region>.amazonaws.com/<repo>`)
+    /// as `repository_uri`, for building pullable references.
+    #[arg(long)]
+    include_repository_uri: bool,
+}
+
+/// The repositories to dump from one region, along with the clients for that region.
+struct RegionTarget {
+    dumper: Dumper,
+    pull_metrics: Option<PullMetricsFetcher>,
+    repositories: Vec<Repository>,
+}
+
+impl Args {
+    fn repo_concurrency(&self) -> usize {
+        self.repo_concurrency
+            .map_or_else(|| self.concurrency.repos(), NonZeroUsize::get)
+    }
+
+    fn image_concurrency(&self) -> usize {
+        self.image_concurrency.map_or_else(
+            || self.concurrency.images(self.repo_concurrency()),
+            NonZeroUsize::get,
+        )
+    }
+}
+
+impl RegionTarget {
+    /// The region records and checkpoint keys are qualified with, set when dumping every
+    /// region.
+    fn qualifying_region(&self) -> Option<&str> {
+        self.dumper
+            .record_region
+            .then(|| self.dumper.region())
+            .flatten()
+    }
+}
+
+/// A repository written by --list-repos-only.
+#[derive(Serialize)]
+struct RepositoryListing<'a> {
+    repository_name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<&'a str>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that every record in a dump parses and has the current schema version,
+    /// without contacting AWS. `.gz` and `.zst` files are decompressed. Exits non-zero if
+    /// any record is malformed.
+    ValidateDump { file: PathBuf },
+    /// Check the credentials allow every IAM action a dump needs, by making a minimal
+    /// call for each. Exits non-zero if a required action is denied or can't be checked.
+    /// Pass --registry-id, --fetch-config, --include-scan-findings or --repo-tags to check
+    /// the registry and optional features the dump will use.
+    CheckPermissions {
+        /// Repository to probe image permissions against. Defaults to the first listed.
+        #[arg(long)]
+        repository: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProgressBy {
+    Repos,
+    Images,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    Compact,
+    Json,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let args = Args::parse_from(config_file::args_with_config(Args::command())?);
+    let started = Instant::now();
 
-    let indicatif_layer = IndicatifLayer::new().with_max_progress_bars(14, None);
+    let stderr_is_terminal = std::io::stderr().is_terminal();
+    let indicatif_layer = if args.no_progress || !stderr_is_terminal {
+        progress::disable();
+        None
+    } else {
+        Some(IndicatifLayer::new().with_max_progress_bars(14, None))
+    };
+    let log_writer = match &indicatif_layer {
+        Some(layer) => BoxMakeWriter::new(layer.get_stderr_writer()),
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+    let default_level = match args.verbose {
+        _ if args.quiet => Level::WARN,
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
     let env_builder = EnvFilter::builder()
-        .with_default_directive(Directive::from(Level::INFO))
+        .with_default_directive(Directive::from(default_level))
         .from_env()?;
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_thread_names(true)
+        .with_writer(log_writer);
+    let fmt_layer: Box<dyn Layer<Registry> + Send + Sync> = match args.log_format {
+        LogFormat::Compact => Box::new(fmt_layer.compact().with_ansi(stderr_is_terminal)),
+        LogFormat::Json => Box::new(fmt_layer.json()),
+    };
+    #[cfg(feature = "otlp")]
+    let otlp_exporter = args
+        .otlp_endpoint
+        .as_deref()
+        .map(otlp::OtlpExporter::new)
+        .transpose()?;
+    #[cfg(feature = "otlp")]
+    let otlp_layer = otlp_exporter.as_ref().map(otlp::OtlpExporter::layer);
+    #[cfg(not(feature = "otlp"))]
+    let otlp_layer: Option<tracing_subscriber::layer::Identity> = None;
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer()
-                .compact()
-                .with_thread_names(true)
-                .with_writer(indicatif_layer.get_stderr_writer()),
-        )
+        .with(fmt_layer)
         .with(indicatif_layer)
+        .with(otlp_layer)
         .with(env_builder)
         .init();
 
+    match &args.command {
+        Some(Command::ValidateDump { file }) => return validate_dump::validate_dump(file),
+        Some(Command::CheckPermissions { repository }) => {
+            let shared_config = load_aws_config(&args).await?;
+            let features = check_permissions::Features {
+                registry_id: args.registry_id.clone(),
+                fetch_config: args.fetch_config,
+                scan_findings: args.include_scan_findings,
+                repo_tags: args.repo_tags,
+            };
+            return check_permissions::check_permissions(
+                &shared_config,
+                repository.clone(),
+                &features,
+            )
+            .await;
+        }
+        None => {}
+    }
+    if args.framed_gzip && !args.outputs.iter().all(|path| output::is_file(path)) {
+        bail!("--framed-gzip needs file outputs to write the indexes alongside");
+    }
+    if args.output_format == OutputFormat::Csv
+        && (args.fields.is_some() || args.oci_refs || args.global_sort)
+    {
+        bail!("--output-format csv can't be combined with --fields, --oci-refs or --global-sort");
+    }
+    if args.output_format == OutputFormat::Json
+        && (args.framed_gzip || args.checkpoint_file.is_some())
+    {
+        bail!("--output-format json can't be combined with --framed-gzip or --checkpoint-file");
+    }
+    if args.resume
+        && (args.framed_gzip
+            || args.compress.is_some()
+            || args.outputs.iter().any(|path| s3_location(path).is_some())
+            || args
+                .outputs
+                .iter()
+                .any(|path| OutputCompression::from_extension(path) != OutputCompression::None))
+    {
+        bail!("--resume needs uncompressed outputs to append to");
+    }
+    if args.split_by_repo && (args.outputs.len() != 1 || !output::is_file(&args.outputs[0])) {
+        bail!("--split-by-repo needs a single output directory");
+    }
+    let baseline = match &args.baseline {
+        Some(path) => {
+            let baseline = Baseline::load(path)?;
+            info!(
+                "Loaded {} baseline images from {}",
+                baseline.len(),
+                path.display()
+            );
+            Some(baseline)
+        }
+        None => None,
+    };
+
     info!("Started");
+    info!(
+        "Concurrency {}: {} repositories at once, {} requests per repository",
+        args.concurrency,
+        args.repo_concurrency(),
+        args.image_concurrency()
+    );
+
+    let shared_config = load_aws_config(&args).await?;
+
+    let include_filter = with_glob_file(args.include.clone(), args.include_file.as_deref())?
+        .map(|globs| build_globset("--include", globs, args.case_insensitive))
+        .transpose()?;
+    let exclude_filter = with_glob_file(args.exclude.clone(), args.exclude_file.as_deref())?
+        .map(|globs| build_globset("--exclude", globs, args.case_insensitive))
+        .transpose()?;
+
+    let mut image_filter = ImageFilter::default();
+    if let Some(path) = &args.exclude_digests_from {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        image_filter.excluded_digests =
+            DigestSet::parse(&contents).with_context(|| format!("Parsing {}", path.display()))?;
+        info!(
+            "Loaded {} excluded digests",
+            image_filter.excluded_digests.len()
+        );
+    }
+    image_filter.tag_include = args
+        .tag_include
+        .clone()
+        .map(|globs| build_globset("--tag-include", globs, args.case_insensitive))
+        .transpose()?;
+    image_filter.tag_exclude = args
+        .tag_exclude
+        .clone()
+        .map(|globs| build_globset("--tag-exclude", globs, args.case_insensitive))
+        .transpose()?;
+    if let (Some(after), Some(before)) = (args.pushed_after, args.pushed_before) {
+        if after >= before {
+            bail!("--pushed-after ({after}) must be earlier than --pushed-before ({before})");
+        }
+    }
+    image_filter.stale_since = args.stale_since;
+    image_filter.include_never_pulled = args.include_never_pulled;
+    image_filter.pushed_after = args.pushed_after;
+    image_filter.pushed_before = args.pushed_before;
+    if let Some(expression) = &args.filter {
+        info!("Filtering images with {:?}", expression.source());
+        image_filter.expression = Some(expression.clone());
+    }
+    let fetch_options = FetchOptions {
+        strict_manifests: args.strict_manifests,
+        retry: RetryPolicy {
+            max_retries: args.max_retries,
+            base_delay: args.retry_base_delay,
+            max_delay: args.retry_max_delay,
+            jitter: args.retry_jitter,
+        },
+        filter: Arc::new(image_filter),
+        raw_manifest_dir: args.raw_manifest_dir.clone(),
+        continue_on_error: args.continue_on_error,
+        registry_id: args.registry_id.clone(),
+        tag_status: if args.tagged_only {
+            Some(TagStatus::Tagged)
+        } else if args.untagged_only {
+            Some(TagStatus::Untagged)
+        } else {
+            None
+        },
+        fetch_config: args.fetch_config,
+        blob_cache: args
+            .blob_cache_dir
+            .as_ref()
+            .map(|dir| BlobCache::new(dir, args.blob_cache_max_bytes)),
+        max_index_depth: args.max_index_depth,
+        fail_on_missing: args.fail_on_missing,
+        rate_limiter: args.max_requests_per_second.map(RateLimiter::new),
+        scan_findings: args.include_scan_findings,
+        verify_digests: args.verify_digests,
+        include_raw_manifest: args.include_raw_manifest,
+        platforms: args.platforms.clone(),
+        metrics: Arc::default(),
+    };
+
+    let regions = if args.all_regions {
+        ECR_REGIONS
+            .iter()
+            .map(|r| Some(Region::from_static(r)))
+            .collect()
+    } else {
+        vec![None]
+    };
+    let mut targets: Vec<RegionTarget> = stream::iter(regions.into_iter().map(|region| {
+        list_region(
+            &args,
+            &shared_config,
+            &fetch_options,
+            region,
+            &include_filter,
+            &exclude_filter,
+        )
+    }))
+    .buffer_unordered(args.repo_concurrency())
+    .try_collect::<Vec<_>>()
+    .await?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if args.dry_run {
+        return dry_run(&targets, &args, &fetch_options).await;
+    }
 
-    let shared_config = aws_config::load_from_env().await;
-    let client = Client::new(&shared_config);
+    let resumed = match &args.checkpoint_file {
+        Some(path) if args.resume => Some(checkpoint::load(path).await?),
+        _ => None,
+    };
+    if let Some(resumed) = &resumed {
+        let mut skipped = 0;
+        for target in &mut targets {
+            let region = target.qualifying_region().map(str::to_string);
+            target.repositories.retain(|repository| {
+                let name = repository.repository_name().unwrap_or_default();
+                let done = resumed
+                    .repositories
+                    .contains(&checkpoint::key(region.as_deref(), name));
+                skipped += usize::from(done);
+                !done
+            });
+            // Images already written from an interrupted repository are excluded like
+            // --exclude-digests-from, so they are never fetched again.
+            let mut filter = None;
+            for repository in &target.repositories {
+                let name = repository.repository_name().unwrap_or_default();
+                let key = checkpoint::key(region.as_deref(), name);
+                let Some(digests) = resumed.images.get(&key) else {
+                    continue;
+                };
+                let filter =
+                    filter.get_or_insert_with(|| (*target.dumper.fetch_options.filter).clone());
+                for digest in digests {
+                    filter.excluded_digests.insert(name, digest);
+                }
+            }
+            if let Some(filter) = filter {
+                target.dumper.fetch_options.filter = Arc::new(filter);
+            }
+        }
+        info!("Resuming: skipping {skipped} repositories already dumped");
+    }
+    let checkpoint = match &args.checkpoint_file {
+        Some(path) => Some(Checkpoint::open(path, resumed.as_ref()).await?),
+        None => None,
+    };
+    let resume_at = resumed.as_ref().map(|resumed| resumed.offset);
 
-    let include_filter = args.include.map(build_globset).transpose()?;
-    let exclude_filter = args.exclude.map(build_globset).transpose()?;
+    let mut writer = RecordWriter::new(args.on_output_failure);
+    if args.output_format == OutputFormat::Json {
+        writer.set_json_array();
+    }
+    let mut s3_client = None;
+    let outputs = if args.split_by_repo {
+        let dir = &args.outputs[0];
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Creating output directory {}", dir.display()))?;
+        &[][..]
+    } else {
+        &args.outputs[..]
+    };
+    for output_path in outputs {
+        let (output_path, compression) = match args.compress {
+            _ if args.framed_gzip => (output_path.clone(), OutputCompression::None),
+            Some(compression)
+                if output::is_file(output_path) || s3_location(output_path).is_some() =>
+            {
+                (compression.with_extension(output_path), compression)
+            }
+            _ => (
+                output_path.clone(),
+                OutputCompression::from_extension(output_path),
+            ),
+        };
+        let output = match s3_location(&output_path) {
+            Some(location) => {
+                let client = s3_client.get_or_insert_with(|| {
+                    // Uploads aren't covered by --max-retries, so keep the SDK's retries.
+                    aws_sdk_s3::Client::from_conf(
+                        aws_sdk_s3::config::Builder::from(&shared_config)
+                            .retry_config(RetryConfig::standard())
+                            .build(),
+                    )
+                });
+                output::open_s3(client, location, compression, args.compress_level)
+            }
+            None => {
+                output::open(
+                    &output_path,
+                    compression,
+                    args.compress_level,
+                    resume_at.filter(|_| output::is_file(&output_path)),
+                )
+                .await?
+            }
+        };
+        let name = output_path.display().to_string();
+        if args.framed_gzip {
+            let mut index_path = output_path.clone().into_os_string();
+            index_path.push(".idx");
+            writer
+                .add_gzip_framed(name, output, index_path.as_ref())
+                .await?;
+        } else {
+            writer.add(name, output);
+        }
+    }
+    if args.tee {
+        writer.add("stdout".to_string(), output::stdout());
+    }
+    if args.list_repos_only {
+        let result = write_repository_list(&targets, writer, &args).await;
+        s3_upload::wait_for_aborted_uploads().await;
+        return result;
+    }
+    if args.output_format == OutputFormat::Csv && !args.split_by_repo && resume_at.unwrap_or(0) == 0
+    {
+        writer
+            .write_line(&csv_rows::format_row(csv_rows::HEADER)?)
+            .await?;
+    }
+    interrupt::install();
+    if let Some(timeout) = args.timeout {
+        interrupt::stop_after(timeout, started);
+    }
+    let result = run(targets, writer, checkpoint, baseline, &args).await;
+    s3_upload::wait_for_aborted_uploads().await;
+    let duration = started.elapsed();
+    // Written even if the run failed, so a scheduled job's failures are visible.
+    if let Some(path) = &args.metrics_file {
+        prometheus::write(path, &fetch_options.metrics.snapshot(), duration).await?;
+    }
+    let mut summary = result?;
+    summary.duration_seconds = duration.as_secs_f64();
+    if !args.quiet {
+        eprintln!("{summary}");
+    }
+    if let Some(path) = &args.summary_file {
+        tokio::fs::write(path, serde_json::to_vec_pretty(&summary)?)
+            .await
+            .with_context(|| format!("Writing summary to {}", path.display()))?;
+    }
+    match interrupt::stopped() {
+        Some(Stop::Interrupted) => {
+            // Exiting skips destructors, so flush any spans first.
+            #[cfg(feature = "otlp")]
+            drop(otlp_exporter);
+            std::process::exit(interrupt::EXIT_CODE)
+        }
+        Some(Stop::TimedOut) => bail!(
+            "Timed out after {}, only some repositories were dumped",
+            humantime::format_duration(args.timeout.unwrap_or_default())
+        ),
+        None => Ok(()),
+    }
+}
 
-    let repo_lister = RepositoryLister::new(client.clone(), include_filter, exclude_filter);
-    let repo_names = repo_lister.list().await?;
-    info!("Discovered {} repositories", repo_names.len());
-    debug!("Repo names: {:?}", repo_names);
+/// The shared AWS config, from the environment unless overridden by `--profile`,
+/// `--region` or `--endpoint-url`, assuming `--assume-role-arn` if given.
+async fn load_aws_config(args: &Args) -> anyhow::Result<SdkConfig> {
+    // ECR and CloudWatch calls are retried by --max-retries, which also counts every
+    // attempt. The SDK's own retries would repeat each of those attempts again.
+    let mut loader =
+        aws_config::defaults(BehaviorVersion::latest()).retry_config(RetryConfig::disabled());
+    if let Some(profile) = &args.profile {
+        loader = loader.profile_name(profile);
+    }
+    if let Some(region) = &args.region {
+        loader = loader.region(Region::new(region.clone()));
+    }
+    if let Some(endpoint_url) = &args.endpoint_url {
+        loader = loader.endpoint_url(endpoint_url);
+    }
+    let config = loader.load().await;
+    let Some(role_arn) = &args.assume_role_arn else {
+        return Ok(config);
+    };
+    let provider = AssumeRoleProvider::builder(role_arn)
+        .session_name(&args.role_session_name)
+        .configure(&config)
+        .build()
+        .await;
+    // Assume the role up front, so a bad ARN or trust policy fails here rather than on
+    // the first ECR call.
+    provider
+        .provide_credentials()
+        .await
+        .with_context(|| format!("Assuming role {role_arn}"))?;
+    info!("Assumed role {role_arn}");
+    Ok(config
+        .into_builder()
+        .credentials_provider(SharedCredentialsProvider::new(provider))
+        .build())
+}
+
+#[instrument(skip_all, fields(region = ?region))]
+async fn list_region(
+    args: &Args,
+    shared_config: &SdkConfig,
+    fetch_options: &FetchOptions,
+    region: Option<Region>,
+    include_filter: &Option<GlobSet>,
+    exclude_filter: &Option<GlobSet>,
+) -> anyhow::Result<Option<RegionTarget>> {
+    let (client, cloudwatch_client) = match &region {
+        Some(region) => (
+            Client::from_conf(
+                aws_sdk_ecr::config::Builder::from(shared_config)
+                    .region(region.clone())
+                    .build(),
+            ),
+            aws_sdk_cloudwatch::Client::from_conf(
+                aws_sdk_cloudwatch::config::Builder::from(shared_config)
+                    .region(region.clone())
+                    .build(),
+            ),
+        ),
+        None => (
+            Client::new(shared_config),
+            aws_sdk_cloudwatch::Client::new(shared_config),
+        ),
+    };
+
+    let retry = &fetch_options.retry;
+    let mut repo_lister = RepositoryLister::new(
+        client.clone(),
+        include_filter.clone(),
+        exclude_filter.clone(),
+    );
+    repo_lister.sort = !args.no_sort_repos;
+    repo_lister.registry_id.clone_from(&args.registry_id);
+    repo_lister.retry = retry.clone();
+    repo_lister
+        .rate_limiter
+        .clone_from(&fetch_options.rate_limiter);
+    repo_lister.metrics = fetch_options.metrics.clone();
+    repo_lister.include_regex.clone_from(&args.include_regex);
+    repo_lister.exclude_regex.clone_from(&args.exclude_regex);
+    let repositories = match (repo_lister.list_repositories().await, &region) {
+        (Ok(repositories), _) => repositories,
+        (Err(e), Some(region)) => {
+            warn!("Skipping region {region}: {e:#}");
+            return Ok(None);
+        }
+        (Err(e), None) => return Err(e),
+    };
+    info!("Discovered {} repositories", repositories.len());
+    debug!(
+        "Repo names: {:?}",
+        repositories
+            .iter()
+            .map(|r| r.repository_name())
+            .collect::<Vec<_>>()
+    );
 
-    let output = tokio::io::BufWriter::new(tokio::fs::File::create(args.output).await?);
-    run(client, repo_names, output, args.concurrency).await?;
+    let pull_metrics = args.pull_metrics.then(|| {
+        let mut fetcher = PullMetricsFetcher::new(cloudwatch_client, args.pull_metrics_days);
+        fetcher.retry = retry.clone();
+        fetcher
+    });
 
+    let mut dumper = Dumper::new_with_concurrency(client, args.image_concurrency());
+    dumper.fetch_options = fetch_options.clone();
+    dumper.page_size = args.page_size;
+    dumper.batch_size = args.batch_size.into();
+    dumper.record_region = region.is_some();
+    dumper.min_repo_images = args.min_repo_images;
+    dumper.signing_coverage = args.include_referrers;
+    dumper.dedupe_records = args.dedupe_records;
+    dumper.warn_large_repo = args.warn_large_repo;
+    dumper.fail_on_repo_errors = args.fail_on_repo_errors;
+    Ok(Some(RegionTarget {
+        dumper,
+        pull_metrics,
+        repositories,
+    }))
+}
+
+/// Count what a dump would cover for --dry-run.
+async fn dry_run(
+    targets: &[RegionTarget],
+    args: &Args,
+    fetch_options: &FetchOptions,
+) -> anyhow::Result<()> {
+    let mut repositories = 0;
+    let mut images = 0;
+    let mut batch_get_image_calls = 0;
+    for target in targets {
+        let repo_names: Vec<RepositoryName> = target
+            .repositories
+            .iter()
+            .filter_map(|r| r.repository_name.clone())
+            .collect();
+        for (name, count) in target
+            .dumper
+            .count_images_by_repository(&repo_names)
+            .await?
+        {
+            info!("{name}: {count} images");
+            repositories += 1;
+            images += count;
+            batch_get_image_calls += count.div_ceil(usize::from(args.batch_size));
+        }
+    }
+    let api_calls = fetch_options.metrics.snapshot().api_calls;
+    println!("Repositories:              {repositories}");
+    println!("Images:                    {images}");
+    println!("Listing calls made:        {api_calls}");
+    println!("BatchGetImage calls (min): {batch_get_image_calls}");
+    Ok(())
+}
+
+/// Write the listed repositories for --list-repos-only.
+async fn write_repository_list(
+    targets: &[RegionTarget],
+    mut writer: RecordWriter<OutputWriter>,
+    args: &Args,
+) -> anyhow::Result<()> {
+    let csv = args.output_format == OutputFormat::Csv;
+    if csv {
+        let header = if args.all_regions {
+            &["repository", "region"][..]
+        } else {
+            &["repository"][..]
+        };
+        writer.write_line(&csv_rows::format_row(header)?).await?;
+    }
+    let mut count = 0;
+    for target in targets {
+        let region = target.qualifying_region();
+        for repository in &target.repositories {
+            let repository_name = repository.repository_name().unwrap_or_default();
+            if csv {
+                let row = [repository_name].into_iter().chain(region);
+                writer.write_line(&csv_rows::format_row(row)?).await?;
+            } else {
+                writer
+                    .write(&RepositoryListing {
+                        repository_name,
+                        region,
+                    })
+                    .await?;
+            }
+            count += 1;
+        }
+    }
+    writer.finish().await?;
+    info!("Listed {count} repositories");
     Ok(())
 }
 
 #[instrument(skip_all)]
 async fn run(
-    client: Client,
-    repo_names: Vec<String>,
-    mut output: tokio::io::BufWriter<tokio::fs::File>,
-    concurrency: usize,
-) -> anyhow::Result<()> {
-    let span = progress::set_span_progress("repos", repo_names.len());
-
-    let mut stream = stream::iter(
-        repo_names
-            .into_iter()
-            .map(|val| fetch_repo(client.clone(), val, concurrency)),
-    )
-    .buffer_unordered(concurrency);
-
-    let mut buffer = vec![];
-    while let Some(repo_result) = stream.next().await {
-        let (name, repo_images) = repo_result?;
+    targets: Vec<RegionTarget>,
+    mut writer: RecordWriter<OutputWriter>,
+    mut checkpoint: Option<Checkpoint>,
+    mut baseline: Option<Baseline>,
+    args: &Args,
+) -> anyhow::Result<RunSummary> {
+    let repo_count = targets.iter().map(|t| t.repositories.len()).sum();
+    // By images, each repository advances the bar by its counted images once it is done,
+    // even if it was skipped or fewer of its images were written, so the bar ends full.
+    let mut image_counts = HashMap::new();
+    let span = match args.progress_by {
+        ProgressBy::Images if progress::is_enabled() => {
+            for target in &targets {
+                let repo_names: Vec<RepositoryName> = target
+                    .repositories
+                    .iter()
+                    .filter_map(|r| r.repository_name.clone())
+                    .collect();
+                for (name, count) in target
+                    .dumper
+                    .count_images_by_repository(&repo_names)
+                    .await?
+                {
+                    image_counts.insert(checkpoint::key(target.qualifying_region(), &name), count);
+                }
+            }
+            let total: usize = image_counts.values().sum();
+            info!("Counted {total} images across {repo_count} repositories");
+            progress::set_span_progress("images", total)
+        }
+        _ => progress::set_span_progress("repos", repo_count),
+    };
+    let progress_of = |target: &RegionTarget, name: &str| match args.progress_by {
+        ProgressBy::Repos => 1,
+        ProgressBy::Images => image_counts
+            .get(&checkpoint::key(target.qualifying_region(), name))
+            .map_or(0, |&count| count as u64),
+    };
+
+    let mut stream = stream::iter(targets.iter().flat_map(|target| {
+        target
+            .repositories
+            .iter()
+            .map(move |repository| async move {
+                let repo_name = repository.repository_name().unwrap().to_string();
+                let fetched = match args.repo_timeout {
+                    Some(repo_timeout) => {
+                        let fetch = target.dumper.fetch_repo(repo_name.clone());
+                        match tokio::time::timeout(repo_timeout, fetch).await {
+                            Ok(fetched) => fetched?,
+                            Err(_) => {
+                                warn!(
+                                    "Skipping {repo_name}: not fetched within {}",
+                                    humantime::format_duration(repo_timeout)
+                                );
+                                target.dumper.fetch_options.metrics.add_errors(1);
+                                None
+                            }
+                        }
+                    }
+                    None => target.dumper.fetch_repo(repo_name).await?,
+                };
+                let Some(repo) = fetched else {
+                    return anyhow::Ok((target, repository, None));
+                };
+                let repo_pull_metrics = match &target.pull_metrics {
+                    Some(fetcher) => Some(fetcher.fetch(&repo.repository_name).await?),
+                    None => None,
+                };
+                let repo_tags = match repository.repository_arn() {
+                    Some(arn) if args.repo_tags => Some(target.dumper.repository_tags(arn).await?),
+                    _ => None,
+                };
+                anyhow::Ok((
+                    target,
+                    repository,
+                    Some((repo, repo_pull_metrics, repo_tags)),
+                ))
+            })
+    }))
+    .buffer_unordered(args.repo_concurrency());
+
+    let include_repository_uri = args.include_repository_uri
+        || args
+            .fields
+            .as_ref()
+            .is_some_and(|fields| fields.contains(&Field::RepositoryUri));
+    let mut layer_compression: BTreeMap<LayerCompression, usize> = BTreeMap::new();
+    let mut summary = RunSummary::default();
+    let mut sorter = args
+        .global_sort
+        .then(|| GlobalSorter::new(args.global_sort_buffer));
+    let mut tag_map = args
+        .tag_map
+        .as_ref()
+        .map(|_| TagMap::new(args.tag_map_untagged));
+    let mut layer_stats = args.layer_stats.then(LayerStats::default);
+    // Repositories whose baseline images can't be reported as removed.
+    let mut incomplete = HashSet::new();
+    let mut empty_repos = vec![];
+    loop {
+        // Stopping here, between repositories, leaves the outputs with whole records.
+        let repo_result = tokio::select! {
+            biased;
+            _ = interrupt::interrupted() => {
+                summary.interrupted = true;
+                break;
+            }
+            repo_result = stream.next() => repo_result,
+        };
+        let Some(repo_result) = repo_result else {
+            break;
+        };
+        let (target, repository, fetched) = repo_result?;
+        let Some((repo, repo_pull_metrics, repo_tags)) = fetched else {
+            let name = repository.repository_name().unwrap_or_default();
+            incomplete.insert(name.to_string());
+            span.pb_inc(progress_of(target, name));
+            continue;
+        };
+        let FetchedRepository {
+            repository_name: name,
+            images: mut repo_images,
+            api_calls: repo_api_calls,
+            signing,
+            partial,
+            failures,
+        } = repo;
+        summary.repositories += 1;
+        summary.missing_manifests += failures.len();
+        if !args.no_sort {
+            sort_images(&mut repo_images);
+        }
+        let region = target.qualifying_region().unwrap_or_default();
+        let checkpoint_key = checkpoint::key(target.qualifying_region(), &name);
+        let already_written = match &mut checkpoint {
+            Some(checkpoint) => checkpoint.take_written_images(&checkpoint_key),
+            None => HashSet::new(),
+        };
+        if !already_written.is_empty() {
+            info!(
+                "Resuming {name}: skipped {} images already written",
+                already_written.len()
+            );
+        }
+        if partial {
+            incomplete.insert(name.clone());
+        }
+        summary.api_calls += repo_api_calls;
+        let mut repo_writer = match args.split_by_repo {
+            true => Some(open_repo_writer(args, target.dumper.region(), &name).await?),
+            false => None,
+        };
+        let out = repo_writer.as_mut().unwrap_or(&mut writer);
         info!(
             "Discovered {} images in repository {name}",
             repo_images.len()
         );
-        for image in repo_images {
-            serde_json::to_writer(&mut buffer, &image)?;
-            buffer.push(b'\n');
-            output.write_all(&buffer).await?;
-            buffer.clear();
+        if repo_images.is_empty() && already_written.is_empty() {
+            empty_repos.push(checkpoint_key.clone());
+        }
+        if let Some(signing) = &signing {
+            info!(
+                "{name}: {} of {} images signed, {} attested, {} with an SBOM",
+                signing.signed, signing.image_count, signing.attested, signing.with_sbom
+            );
+        }
+        // Repository records have no CSV form, and a resumed repository's was already written.
+        if args.output_format != OutputFormat::Csv
+            && already_written.is_empty()
+            && (partial
+                || !failures.is_empty()
+                || args.pull_metrics
+                || args.include_kms_encryption_context
+                || args.repo_metadata
+                || args.repo_tags
+                || args.include_referrers)
+        {
+            let record = RepositoryRecord {
+                repository: RepositorySummary {
+                    repository_name: name.clone(),
+                    region: target
+                        .dumper
+                        .record_region
+                        .then(|| target.dumper.region().map(str::to_string))
+                        .flatten(),
+                    image_count: repo_images.len(),
+                    pull_metrics: repo_pull_metrics,
+                    encryption: args
+                        .include_kms_encryption_context
+                        .then(|| RepositoryEncryption::from_repository(repository))
+                        .flatten(),
+                    metadata: args
+                        .repo_metadata
+                        .then(|| RepositoryMetadata::from_repository(repository)),
+                    tags: repo_tags,
+                    signing,
+                    partial,
+                    failures,
+                },
+            };
+            emit(out, &mut sorter, (region, &name, ""), &record).await?;
+        }
+        let mut unrecorded = vec![];
+        for image in &mut repo_images {
+            if let (Some(checkpoint), Some(every)) = (&mut checkpoint, args.checkpoint_images) {
+                if unrecorded.len() >= every.get() {
+                    out.flush().await?;
+                    checkpoint
+                        .record_images(out.written(), &checkpoint_key, &unrecorded)
+                        .await?;
+                    unrecorded.clear();
+                }
+                unrecorded.push(image.image.manifest_digest.clone());
+            }
+            summary.add_image(image);
+            if args.stale_since.is_some() {
+                image.days_since_pull = image
+                    .image
+                    .last_recorded_pull_time
+                    .map(|pulled_at| (Utc::now() - pulled_at).num_days());
+            }
+            if include_repository_uri {
+                image.repository_uri = repository.repository_uri().map(str::to_string);
+            }
+            summary.invalid_manifests += image
+                .manifests
+                .iter()
+                .filter(|m| !m.validation_issues.is_empty())
+                .count();
+            for compression in image.manifests.iter().flat_map(|m| &m.layer_compression) {
+                *layer_compression.entry(*compression).or_default() += 1;
+            }
+            if let Some(tag_map) = &mut tag_map {
+                tag_map.add(image);
+            }
+            if let Some(layer_stats) = &mut layer_stats {
+                layer_stats.add(image);
+            }
+            if let Some(baseline) = &mut baseline {
+                if baseline.is_unchanged(image, args.baseline_tag_changes) {
+                    summary.unchanged_images += 1;
+                    continue;
+                }
+            }
+            let key = (region, name.as_str(), image.image.manifest_digest.as_str());
+            match &args.fields {
+                _ if args.output_format == OutputFormat::Csv => {
+                    for row in csv_rows::rows(image)? {
+                        out.write_line(&row).await?;
+                    }
+                }
+                Some(fields) => {
+                    emit(out, &mut sorter, key, &fields::project(image, fields)).await?
+                }
+                None if args.oci_refs => {
+                    emit(out, &mut sorter, key, &OciReference::new(image)).await?
+                }
+                None => {
+                    if args.emit_timestamps {
+                        image.emitted_at = Some(Utc::now());
+                    }
+                    emit(out, &mut sorter, key, &*image).await?
+                }
+            }
+        }
+        span.pb_inc(progress_of(target, &name));
+        match repo_writer {
+            Some(repo_writer) => repo_writer.finish().await?,
+            None => writer.flush().await?,
+        }
+        if let Some(checkpoint) = &mut checkpoint {
+            checkpoint.record(writer.written(), &checkpoint_key).await?;
+        }
+    }
+    if let Some(sorter) = sorter {
+        sorter.write_to(&mut writer).await?;
+    }
+    if let Some(layer_stats) = &layer_stats {
+        let layer_stats = layer_stats.summary();
+        info!(
+            "{} unique layers totalling {} bytes, {} bytes referenced: a dedup ratio of {:.2}",
+            layer_stats.unique_layers,
+            layer_stats.unique_bytes,
+            layer_stats.referenced_bytes,
+            layer_stats.dedup_ratio
+        );
+        if args.output_format != OutputFormat::Csv {
+            writer.write(&LayerStatsRecord { layer_stats }).await?;
         }
-        span.pb_inc(1);
-        output.flush().await?;
     }
-    output.flush().await?;
-    Ok(())
+    writer.finish().await?;
+    if let (Some(path), Some(tag_map)) = (&args.tag_map, &tag_map) {
+        tokio::fs::write(path, serde_json::to_vec_pretty(tag_map)?)
+            .await
+            .with_context(|| format!("Writing tag map to {}", path.display()))?;
+        info!("Wrote tag map to {}", path.display());
+    }
+    empty_repos.sort();
+    summary.empty_repositories = empty_repos.len();
+    if !empty_repos.is_empty() {
+        warn!(
+            "{} repositories had no images: {}",
+            empty_repos.len(),
+            empty_repos.join(", ")
+        );
+    }
+    if let Some(path) = &args.empty_repos_file {
+        let contents: String = empty_repos.iter().map(|name| format!("{name}\n")).collect();
+        tokio::fs::write(path, contents)
+            .await
+            .with_context(|| format!("Writing empty repositories to {}", path.display()))?;
+    }
+    if let (Some(path), Some(baseline)) = (&args.removed_file, &baseline) {
+        if summary.interrupted {
+            warn!("Not writing {}: the dump was interrupted", path.display());
+        } else {
+            let removed = baseline.write_removed(path, &incomplete).await?;
+            info!("Wrote {removed} removed images to {}", path.display());
+        }
+    }
+
+    let api_calls = summary.api_calls;
+    info!(
+        "Made {} DescribeImages and {} BatchGetImage calls in total",
+        api_calls.describe_images, api_calls.batch_get_image
+    );
+    if summary.missing_manifests > 0 {
+        warn!(
+            "BatchGetImage could not return {} manifests",
+            summary.missing_manifests
+        );
+    }
+    if args.fetch_config {
+        info!(
+            "Made {} GetDownloadUrlForLayer calls in total",
+            api_calls.get_download_url_for_layer
+        );
+    }
+    if args.include_scan_findings {
+        info!(
+            "Made {} DescribeImageScanFindings calls in total",
+            api_calls.describe_image_scan_findings
+        );
+    }
+    info!(
+        "Layer compression: {}",
+        layer_compression
+            .iter()
+            .map(|(compression, count)| format!("{compression}={count}"))
+            .join(", ")
+    );
+    if args.strict_manifests {
+        info!(
+            "{} manifests failed strict schema validation",
+            summary.invalid_manifests
+        );
+    }
+    Ok(summary)
 }
 
-#[instrument(skip(client))]
-async fn fetch_repo(
-    client: Client,
-    repo_name: RepositoryName,
-    concurrency: usize,
-) -> anyhow::Result<(RepositoryName, Vec<ImageWithManifests>)> {
-    let image_fetcher = ImageFetcher::new_with_concurrency(client, repo_name.clone(), concurrency);
-    let images = image_fetcher.fetch_images().await?;
-    debug!("Found {} images:", images.len());
-    let resolved = image_fetcher
-        .resolve_images(&images)
-        .await
-        .with_context(|| format!("Resolving {repo_name}"))?;
-    debug!("Resolved {} images with manifests", resolved.len());
-    Ok((repo_name, resolved))
+/// Order images by repository, push time and digest, and each image's manifests by
+/// digest, so that unchanged registries dump identically.
+fn sort_images(images: &mut [ImageWithManifests]) {
+    images.sort_by(|a, b| {
+        (
+            &a.image.repository_name,
+            a.image.image_pushed_at,
+            &a.image.manifest_digest,
+        )
+            .cmp(&(
+                &b.image.repository_name,
+                b.image.image_pushed_at,
+                &b.image.manifest_digest,
+            ))
+    });
+    for image in images {
+        image
+            .manifests
+            .sort_by_cached_key(|m| m.descriptor.as_ref().map(|d| d.digest().to_string()));
+    }
+}
+
+/// With --split-by-repo, create the file a repository's records are written to.
+async fn open_repo_writer(
+    args: &Args,
+    region: Option<&str>,
+    repository_name: &str,
+) -> anyhow::Result<RecordWriter<OutputWriter>> {
+    let mut path = args.outputs[0].clone();
+    if args.all_regions {
+        if let Some(region) = region {
+            path.push(region);
+            tokio::fs::create_dir_all(&path)
+                .await
+                .with_context(|| format!("Creating output directory {}", path.display()))?;
+        }
+    }
+    let extension = match args.output_format {
+        OutputFormat::Jsonl => "jsonl",
+        OutputFormat::Json => "json",
+        OutputFormat::Csv => "csv",
+    };
+    path.push(format!(
+        "{}.{extension}",
+        repository_name.replace('/', "%2F")
+    ));
+    let compression = args.compress.unwrap_or(OutputCompression::None);
+    let path = compression.with_extension(&path);
+    let output = output::open(&path, compression, args.compress_level, None).await?;
+    let mut writer = RecordWriter::new(SinkFailure::Abort);
+    if args.output_format == OutputFormat::Json {
+        writer.set_json_array();
+    }
+    writer.add(path.display().to_string(), output);
+    if args.output_format == OutputFormat::Csv {
+        writer
+            .write_line(&csv_rows::format_row(csv_rows::HEADER)?)
+            .await?;
+    }
+    Ok(writer)
+}
+
+/// Write `record` now, or hand it to the sorter under `key` with --global-sort.
+async fn emit<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut RecordWriter<W>,
+    sorter: &mut Option<GlobalSorter>,
+    (region, repository_name, digest): (&str, &str, &str),
+    record: &T,
+) -> anyhow::Result<()> {
+    match sorter {
+        Some(sorter) => sorter.push(region, repository_name, digest, record).await,
+        None => writer.write(record).await,
+    }
+}
+
+fn parse_rfc3339(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)
+        .with_context(|| format!("{value:?} is not an RFC3339 time like 2024-01-01T00:00:00Z"))?
+        .with_timezone(&Utc))
+}
+
+fn parse_stale_since(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(age) = humantime::parse_duration(value) {
+        return chrono::Duration::from_std(age)
+            .ok()
+            .and_then(|age| Utc::now().checked_sub_signed(age))
+            .with_context(|| format!("{value:?} is too long ago"));
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    parse_rfc3339(value)
+        .with_context(|| format!("{value:?} is not an age like 90d, a date or an RFC3339 time"))
 }
 
-fn build_globset(globs: Vec<Glob>) -> anyhow::Result<GlobSet> {
+/// `globs` plus those read from `path`, or `None` if neither is given.
+fn with_glob_file(
+    globs: Option<Vec<String>>,
+    path: Option<&Path>,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let Some(path) = path else {
+        return Ok(globs);
+    };
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut globs = globs.unwrap_or_default();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        Glob::new(line)
+            .with_context(|| format!("{} line {}: invalid glob", path.display(), idx + 1))?;
+        globs.push(line.to_string());
+    }
+    Ok(Some(globs))
+}
+
+fn build_globset(
+    flag: &str,
+    patterns: Vec<String>,
+    case_insensitive: bool,
+) -> anyhow::Result<GlobSet> {
     let mut builder = globset::GlobSetBuilder::new();
-    for glob in globs {
+    let count = patterns.len();
+    for (idx, pattern) in patterns.iter().enumerate() {
+        if pattern.trim().is_empty() {
+            bail!(
+                "{flag} pattern #{} is empty ({pattern:?}) and would never match",
+                idx + 1
+            );
+        }
+        if !pattern.contains(['*', '?', '[', '{']) {
+            warn!("{flag} pattern {pattern:?} has no wildcards and only matches that exact name");
+        }
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .with_context(|| format!("Invalid {flag} pattern {pattern:?}"))?;
         builder.add(glob);
     }
-    Ok(builder.build()?)
+    info!("Loaded {count} {flag} patterns");
+    builder
+        .build()
+        .with_context(|| format!("Building {flag} patterns"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(
+            parse_rfc3339("2024-01-01T12:00:00+01:00").unwrap(),
+            "2024-01-01T11:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            parse_stale_since("2024-01-01").unwrap(),
+            "2024-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        let age = Utc::now() - parse_stale_since("90d").unwrap();
+        assert!((age - chrono::Duration::days(90)).num_seconds().abs() < 60);
+    }
+
+    #[test]
+    fn rejects_out_of_range_times() {
+        assert!(parse_rfc3339("99999-01-01T00:00:00Z").is_err());
+        assert!(parse_rfc3339("2024-13-01T00:00:00Z").is_err());
+        assert!(parse_stale_since("2024-02-30").is_err());
+        assert!(parse_stale_since("100000000years").is_err());
+        assert!(parse_stale_since("18446744073709551615s").is_err());
+    }
 }
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Running totals for a dump, updated by every fetcher sharing them as work completes.
+#[derive(Debug, Default)]
+pub struct DumpMetrics {
+    repositories: AtomicU64,
+    images: AtomicU64,
+    bytes: AtomicU64,
+    api_calls: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// A point-in-time copy of [`DumpMetrics`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MetricsSnapshot {
+    /// Repositories fully fetched.
+    pub repositories: u64,
+    /// Images resolved, counted after `--dedupe-records` merges children into their lists.
+    pub images: u64,
+    /// Layer bytes referenced by those images' manifests.
+    pub bytes: u64,
+    /// ECR calls made, including retries.
+    pub api_calls: u64,
+    /// Failed calls, manifests ECR couldn't return, and repositories that failed.
+    pub errors: u64,
+}
+
+impl DumpMetrics {
+    pub fn add_repository(&self) {
+        self.repositories.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_images(&self, images: u64, bytes: u64) {
+        self.images.fetch_add(images, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_api_call(&self) {
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_errors(&self, errors: u64) {
+        self.errors.fetch_add(errors, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            repositories: self.repositories.load(Ordering::Relaxed),
+            images: self.images.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            api_calls: self.api_calls.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use ecr_dump::images::ImageWithManifests;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+const REF_NAME: &str = "org.opencontainers.image.ref.name";
+const REPOSITORY: &str = "ecr-dump.repository";
+const TAGS: &str = "ecr-dump.tags";
+const PUSHED_AT: &str = "ecr-dump.pushed-at";
+
+/// An image rendered as an OCI descriptor, the shape entries take in an image index or
+/// an image layout's `index.json`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciReference<'a> {
+    media_type: &'a str,
+    digest: &'a str,
+    /// Absent if the manifest could not be fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+    annotations: BTreeMap<&'static str, String>,
+}
+
+impl<'a> OciReference<'a> {
+    /// The first tag becomes the standard `ref.name` annotation; the repository and the
+    /// full tag list are kept under `ecr-dump.*` annotations.
+    pub fn new(image: &'a ImageWithManifests) -> Self {
+        let mut annotations = BTreeMap::new();
+        annotations.insert(REPOSITORY, image.image.repository_name.clone());
+        annotations.insert(PUSHED_AT, image.image.image_pushed_at.to_rfc3339());
+        if let Some(tag) = image.image.image_tags.first() {
+            annotations.insert(REF_NAME, tag.clone());
+            annotations.insert(TAGS, image.image.image_tags.join(","));
+        }
+        Self {
+            media_type: &image.image.manifest_media_type,
+            digest: &image.image.manifest_digest,
+            size: image.manifest_size,
+            annotations,
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Context;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Exports spans to an OTLP/HTTP collector in batches, flushing the rest when dropped.
+pub struct OtlpExporter {
+    provider: SdkTracerProvider,
+}
+
+impl OtlpExporter {
+    /// `endpoint` is the full traces URL, such as `http://localhost:4318/v1/traces`.
+    pub fn new(endpoint: &str) -> anyhow::Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .with_context(|| format!("Creating OTLP exporter for {endpoint}"))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("ecr-dump").build())
+            .build();
+        Ok(Self { provider })
+    }
+
+    /// A layer sending every span to the collector.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("ecr-dump"))
+    }
+}
+
+impl Drop for OtlpExporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to export the remaining spans: {e}");
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use crate::s3_upload::{s3_location, S3Writer};
+use anyhow::{bail, Context};
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use async_compression::Level;
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::{DeserializeOwned, Error as _, SeqAccess, Visitor};
+use serde::{Deserializer as _, Serialize};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tracing::warn;
+
+pub type OutputWriter = BufWriter<Box<dyn AsyncWrite + Send + Unpin>>;
+
+const UNIX_SCHEME: &str = "unix:";
+
+fn unix_socket_path(path: &Path) -> Option<&str> {
+    path.to_str().and_then(|p| p.strip_prefix(UNIX_SCHEME))
+}
+
+fn is_stdout(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Whether `path` refers to a regular file rather than stdout, a socket, S3 or other stream.
+pub fn is_file(path: &Path) -> bool {
+    !is_stdout(path) && unix_socket_path(path).is_none() && s3_location(path).is_none()
+}
+
+/// Open a dump for reading, decompressing it by its `.gz` or `.zst` extension. Every
+/// gzip member or zstd frame is read, so `--framed-gzip` output reads as a whole.
+pub fn open_reader(path: &Path) -> anyhow::Result<Box<dyn Read>> {
+    let file = std::fs::File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    Ok(match OutputCompression::from_extension(path) {
+        OutputCompression::None => Box::new(file),
+        OutputCompression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        OutputCompression::Zstd => Box::new(
+            zstd::Decoder::new(file).with_context(|| format!("Opening {}", path.display()))?,
+        ),
+    })
+}
+
+/// Read the records of a dump written as JSONL or as a `--output-format json` array,
+/// decompressing it by extension, calling `each` with every record and its number from 1.
+///
+/// A JSONL line that isn't valid JSON is passed to `each` as an error. Within an array
+/// there's no way to skip a malformed record, so reading stops at the first one.
+pub fn read_records<T: DeserializeOwned>(
+    path: &Path,
+    mut each: impl FnMut(usize, anyhow::Result<T>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let truncated = "the file is truncated or not valid compressed data";
+    let mut reader = BufReader::new(open_reader(path)?);
+    let first = reader
+        .fill_buf()
+        .with_context(|| format!("Reading {}: {truncated}", path.display()))?
+        .first()
+        .copied();
+    if first != Some(b'[') {
+        for (idx, line) in reader.lines().enumerate() {
+            let number = idx + 1;
+            let line = line.with_context(|| format!("Reading line {number}: {truncated}"))?;
+            each(number, serde_json::from_str(&line).context("Invalid JSON"))?;
+        }
+        return Ok(());
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    deserializer
+        .deserialize_seq(EachRecord {
+            each,
+            record: PhantomData,
+        })
+        .and_then(|()| deserializer.end())
+        .with_context(|| format!("Reading {}", path.display()))
+}
+
+/// Passes each element of a JSON array to a callback as it's deserialized, rather than
+/// collecting them.
+struct EachRecord<T, F> {
+    each: F,
+    record: PhantomData<T>,
+}
+
+impl<'de, T, F> Visitor<'de> for EachRecord<T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(usize, anyhow::Result<T>) -> anyhow::Result<()>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of dump records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        let mut number = 0;
+        while let Some(record) = seq.next_element::<T>()? {
+            number += 1;
+            (self.each)(number, Ok(record)).map_err(|e| A::Error::custom(format!("{e:#}")))?;
+        }
+        Ok(())
+    }
+}
+
+/// How records are written.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One JSON record per line.
+    Jsonl,
+    /// A single JSON array of records, one per line.
+    Json,
+    /// A header row, then one row per platform manifest of each image.
+    Csv,
+}
+
+/// How a file output is compressed.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl OutputCompression {
+    /// Infer compression from a `.gz` or `.zst` extension.
+    pub fn from_extension(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    fn extension(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gz"),
+            Self::Zstd => Some("zst"),
+        }
+    }
+
+    /// `path` with this compression's extension appended, unless it already has it.
+    pub fn with_extension(&self, path: &Path) -> PathBuf {
+        match self.extension() {
+            Some(extension) if path.extension().and_then(|e| e.to_str()) != Some(extension) => {
+                let mut path = path.as_os_str().to_owned();
+                path.push(".");
+                path.push(extension);
+                path.into()
+            }
+            _ => path.to_path_buf(),
+        }
+    }
+
+    fn wrap<W: AsyncWrite + Send + Unpin + 'static>(
+        &self,
+        output: W,
+        level: Option<i32>,
+    ) -> Box<dyn AsyncWrite + Send + Unpin> {
+        let level = level.map_or(Level::Default, Level::Precise);
+        match self {
+            Self::None => Box::new(output),
+            Self::Gzip => Box::new(GzipEncoder::with_quality(output, level)),
+            Self::Zstd => Box::new(ZstdEncoder::with_quality(output, level)),
+        }
+    }
+}
+
+/// Open a sink records are written to. `-` writes to stdout, and paths of the form
+/// `unix:/path/to.sock` connect to a listening Unix domain socket; anything else is
+/// created as a file, compressed with `compression` at `level` (or the codec's default).
+///
+/// With `resume_at`, an existing file is cut back to that length and appended to
+/// instead of being replaced.
+pub async fn open(
+    path: &Path,
+    compression: OutputCompression,
+    level: Option<i32>,
+    resume_at: Option<u64>,
+) -> anyhow::Result<OutputWriter> {
+    if is_stdout(path) {
+        return Ok(stdout());
+    }
+    let writer: Box<dyn AsyncWrite + Send + Unpin> = match unix_socket_path(path) {
+        Some(socket_path) => connect_unix_socket(socket_path).await?,
+        None => {
+            let file = match resume_at {
+                Some(offset) => resume_file(path, offset).await?,
+                None => tokio::fs::File::create(path)
+                    .await
+                    .with_context(|| format!("Creating output file {}", path.display()))?,
+            };
+            compression.wrap(file, level)
+        }
+    };
+    Ok(BufWriter::new(writer))
+}
+
+async fn resume_file(path: &Path, offset: u64) -> anyhow::Result<tokio::fs::File> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Opening output file {}", path.display()))?;
+    let length = file.metadata().await?.len();
+    if length < offset {
+        bail!(
+            "Can't resume {}: it is {length} bytes but the checkpoint expects at least {offset}",
+            path.display()
+        );
+    }
+    file.set_len(offset)
+        .await
+        .with_context(|| format!("Truncating {} to the last checkpoint", path.display()))?;
+    Ok(file)
+}
+
+/// Stream an `s3://bucket/key` output into S3, compressed with `compression` before it
+/// is uploaded.
+pub fn open_s3(
+    client: &aws_sdk_s3::Client,
+    (bucket, key): (&str, &str),
+    compression: OutputCompression,
+    level: Option<i32>,
+) -> OutputWriter {
+    let writer = S3Writer::new(client.clone(), bucket, key);
+    BufWriter::new(compression.wrap(writer, level))
+}
+
+/// Standard output as a sink.
+pub fn stdout() -> OutputWriter {
+    BufWriter::new(Box::new(tokio::io::stdout()))
+}
+
+#[cfg(unix)]
+async fn connect_unix_socket(path: &str) -> anyhow::Result<Box<dyn AsyncWrite + Send + Unpin>> {
+    let stream = tokio::net::UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Connecting to unix socket {path} - is the collector running?"))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+async fn connect_unix_socket(path: &str) -> anyhow::Result<Box<dyn AsyncWrite + Send + Unpin>> {
+    anyhow::bail!(
+        "Cannot connect to unix socket {path}: unix sockets are not supported on this platform"
+    )
+}
+
+/// What to do when writing to one of several outputs fails.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SinkFailure {
+    /// Stop the dump.
+    Abort,
+    /// Stop writing to that output and carry on with the rest, failing only if every
+    /// output has failed.
+    Continue,
+}
+
+/// Serializes records as JSON lines, teeing them into one or more output sinks.
+pub struct RecordWriter<W> {
+    sinks: Vec<Sink<W>>,
+    buffer: Vec<u8>,
+    on_failure: SinkFailure,
+    written: u64,
+    /// Write the records as the elements of a JSON array.
+    json_array: bool,
+    records: u64,
+}
+
+struct Sink<W> {
+    name: String,
+    output: W,
+    framing: Option<GzipFraming>,
+    failed: bool,
+}
+
+/// Writes each record as its own gzip member so any record can be decompressed in
+/// isolation, recording `<offset> <length>` of every member in an index file.
+struct GzipFraming {
+    index: BufWriter<tokio::fs::File>,
+    offset: u64,
+    compressed: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> Sink<W> {
+    async fn write(&mut self, line: &[u8]) -> anyhow::Result<()> {
+        let bytes = match &mut self.framing {
+            Some(framing) => {
+                framing.compressed.clear();
+                let mut encoder = GzEncoder::new(&mut framing.compressed, Compression::default());
+                encoder.write_all(line)?;
+                encoder.finish()?;
+                let length = framing.compressed.len() as u64;
+                framing
+                    .index
+                    .write_all(format!("{} {length}\n", framing.offset).as_bytes())
+                    .await
+                    .context("Writing record index")?;
+                framing.offset += length;
+                &framing.compressed
+            }
+            None => line,
+        };
+        self.output
+            .write_all(bytes)
+            .await
+            .context("Writing record to output")
+    }
+
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        self.output.flush().await.context("Flushing output")?;
+        if let Some(framing) = &mut self.framing {
+            framing
+                .index
+                .flush()
+                .await
+                .context("Flushing record index")?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> anyhow::Result<()> {
+        self.flush().await?;
+        self.output.shutdown().await.context("Closing output")
+    }
+}
+
+impl<W: AsyncWrite + Unpin> RecordWriter<W> {
+    pub fn new(on_failure: SinkFailure) -> Self {
+        Self {
+            sinks: vec![],
+            buffer: vec![],
+            on_failure,
+            written: 0,
+            json_array: false,
+            records: 0,
+        }
+    }
+
+    /// Wrap the records in `[` and `]`, separated by commas, rather than writing JSON lines.
+    pub fn set_json_array(&mut self) {
+        self.json_array = true;
+    }
+
+    pub fn add(&mut self, name: String, output: W) {
+        self.sinks.push(Sink {
+            name,
+            output,
+            framing: None,
+            failed: false,
+        });
+    }
+
+    pub async fn add_gzip_framed(
+        &mut self,
+        name: String,
+        output: W,
+        index_path: &Path,
+    ) -> anyhow::Result<()> {
+        let index = tokio::fs::File::create(index_path)
+            .await
+            .with_context(|| format!("Creating index file {}", index_path.display()))?;
+        self.sinks.push(Sink {
+            name,
+            output,
+            framing: Some(GzipFraming {
+                index: BufWriter::new(index),
+                offset: 0,
+                compressed: vec![],
+            }),
+            failed: false,
+        });
+        Ok(())
+    }
+
+    pub async fn write<T: Serialize>(&mut self, record: &T) -> anyhow::Result<()> {
+        self.start_record();
+        serde_json::to_writer(&mut self.buffer, record)?;
+        self.write_buffer().await
+    }
+
+    /// Write a record that has already been serialized to a single line of JSON.
+    pub async fn write_line(&mut self, line: &str) -> anyhow::Result<()> {
+        self.start_record();
+        self.buffer.extend_from_slice(line.as_bytes());
+        self.write_buffer().await
+    }
+
+    /// In a JSON array, open the array before the first record and put a comma before
+    /// each later one, so there is never a trailing comma.
+    fn start_record(&mut self) {
+        if self.json_array {
+            let separator: &[u8] = if self.records == 0 { b"[\n" } else { b",\n" };
+            self.buffer.extend_from_slice(separator);
+        }
+        self.records += 1;
+    }
+
+    async fn write_buffer(&mut self) -> anyhow::Result<()> {
+        if !self.json_array {
+            self.buffer.push(b'\n');
+        }
+        for sink in &mut self.sinks {
+            if !sink.failed {
+                let result = sink.write(&self.buffer).await;
+                Self::handle(self.on_failure, sink, result)?;
+            }
+        }
+        self.written += self.buffer.len() as u64;
+        self.buffer.clear();
+        self.check_sinks()
+    }
+
+    /// Uncompressed bytes written to each output so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub async fn flush(&mut self) -> anyhow::Result<()> {
+        for sink in &mut self.sinks {
+            if !sink.failed {
+                let result = sink.flush().await;
+                Self::handle(self.on_failure, sink, result)?;
+            }
+        }
+        self.check_sinks()
+    }
+
+    pub async fn finish(mut self) -> anyhow::Result<()> {
+        if self.json_array {
+            let end: &[u8] = if self.records == 0 { b"[]\n" } else { b"\n]\n" };
+            self.buffer.extend_from_slice(end);
+            for sink in &mut self.sinks {
+                if !sink.failed {
+                    let result = sink.write(&self.buffer).await;
+                    Self::handle(self.on_failure, sink, result)?;
+                }
+            }
+            self.buffer.clear();
+            self.check_sinks()?;
+        }
+        for sink in &mut self.sinks {
+            if !sink.failed {
+                let result = sink.finish().await;
+                Self::handle(self.on_failure, sink, result)?;
+            }
+        }
+        self.check_sinks()
+    }
+
+    fn handle(
+        on_failure: SinkFailure,
+        sink: &mut Sink<W>,
+        result: anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        match result {
+            Err(e) if on_failure == SinkFailure::Continue => {
+                warn!("Giving up on output {}: {e:#}", sink.name);
+                sink.failed = true;
+                Ok(())
+            }
+            result => result.with_context(|| format!("Output {}", sink.name)),
+        }
+    }
+
+    fn check_sinks(&self) -> anyhow::Result<()> {
+        if !self.sinks.is_empty() && self.sinks.iter().all(|sink| sink.failed) {
+            bail!("Every output has failed");
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use indicatif::ProgressStyle;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tracing::Span;
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 
@@ -6,8 +7,22 @@ const PBAR_TEMPLATE: &str = "{span_child_prefix} {spinner} {span_name} {msg} {pe
 const SPINNER_TEMPLATE: &str =
     "{span_child_prefix}{span_name} {spinner} {msg} {human_pos} - {per_sec}";
 
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn the helpers below into no-ops, for when no `IndicatifLayer` is installed.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
 pub fn set_span_progress(message: &'static str, length: usize) -> Span {
     let span = Span::current();
+    if !is_enabled() {
+        return span;
+    }
     span.pb_set_message(message);
     span.pb_set_style(&ProgressStyle::with_template(PBAR_TEMPLATE).unwrap());
     span.pb_set_length(length as u64);
@@ -16,6 +31,9 @@ pub fn set_span_progress(message: &'static str, length: usize) -> Span {
 
 pub fn span_set_spinner() -> Span {
     let span = Span::current();
+    if !is_enabled() {
+        return span;
+    }
     span.pb_set_style(&ProgressStyle::with_template(SPINNER_TEMPLATE).unwrap());
     span
 }
@@ -0,0 +1,69 @@
+use anyhow::Context;
+use ecr_dump::metrics::MetricsSnapshot;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Render the run's totals in the Prometheus text format.
+pub fn render(metrics: &MetricsSnapshot, duration: Duration) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        let _ = writeln!(out, "{name} {value}");
+    };
+    metric(
+        "ecr_dump_repositories_total",
+        "counter",
+        "Repositories dumped.",
+        metrics.repositories.to_string(),
+    );
+    metric(
+        "ecr_dump_images_total",
+        "counter",
+        "Images dumped.",
+        metrics.images.to_string(),
+    );
+    metric(
+        "ecr_dump_bytes_total",
+        "counter",
+        "Layer bytes referenced by the dumped images.",
+        metrics.bytes.to_string(),
+    );
+    metric(
+        "ecr_dump_api_calls_total",
+        "counter",
+        "ECR API calls made, including retries.",
+        metrics.api_calls.to_string(),
+    );
+    metric(
+        "ecr_dump_errors_total",
+        "counter",
+        "Failed repositories, listings and manifests.",
+        metrics.errors.to_string(),
+    );
+    metric(
+        "ecr_dump_duration_seconds",
+        "gauge",
+        "How long the run took.",
+        format!("{:.3}", duration.as_secs_f64()),
+    );
+    out
+}
+
+/// Write metrics for the node_exporter textfile collector. The file is written under a
+/// temporary name and renamed into place so the collector never reads a partial file.
+pub async fn write(
+    path: &Path,
+    metrics: &MetricsSnapshot,
+    duration: Duration,
+) -> anyhow::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    tokio::fs::write(&tmp_path, render(metrics, duration))
+        .await
+        .with_context(|| format!("Writing metrics to {}", path.display()))?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .with_context(|| format!("Writing metrics to {}", path.display()))
+}
@@ -0,0 +1,85 @@
+use crate::repos::RepositoryName;
+use crate::retry::RetryPolicy;
+use anyhow::{bail, Context};
+use aws_sdk_cloudwatch::primitives::DateTime;
+use aws_sdk_cloudwatch::types::{Dimension, Statistic};
+use aws_sdk_cloudwatch::Client;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+const NAMESPACE: &str = "AWS/ECR";
+const METRIC_NAME: &str = "RepositoryPullCount";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepositoryPullMetrics {
+    pub window_days: u32,
+    /// `None` when CloudWatch has no datapoints for the repository in the window.
+    pub pull_count: Option<u64>,
+}
+
+/// Reads the `RepositoryPullCount` metric ECR publishes to CloudWatch.
+pub struct PullMetricsFetcher {
+    client: Client,
+    window_days: u32,
+    pub retry: RetryPolicy,
+}
+
+impl PullMetricsFetcher {
+    pub fn new(client: Client, window_days: u32) -> Self {
+        Self {
+            client,
+            window_days,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn fetch(&self, repo_name: &RepositoryName) -> anyhow::Result<RepositoryPullMetrics> {
+        if self.window_days == 0 {
+            bail!("The pull metrics window must be at least a day");
+        }
+        // The whole window is summed into a single datapoint.
+        let period = self
+            .window_days
+            .checked_mul(86400)
+            .and_then(|secs| i32::try_from(secs).ok())
+            .with_context(|| {
+                format!("A {}-day pull metrics window is too long", self.window_days)
+            })?;
+        let end = Utc::now();
+        let start = end - Duration::days(self.window_days.into());
+        let request = self
+            .client
+            .get_metric_statistics()
+            .namespace(NAMESPACE)
+            .metric_name(METRIC_NAME)
+            .dimensions(
+                Dimension::builder()
+                    .name("RepositoryName")
+                    .value(repo_name)
+                    .build(),
+            )
+            .start_time(DateTime::from_secs(start.timestamp()))
+            .end_time(DateTime::from_secs(end.timestamp()))
+            .period(period)
+            .statistics(Statistic::Sum);
+        let response = self
+            .retry
+            .retry("GetMetricStatistics", || request.clone().send())
+            .await
+            .with_context(|| format!("Fetching {METRIC_NAME} for {repo_name}"))?;
+
+        let datapoints = response.datapoints();
+        let pull_count = if datapoints.is_empty() {
+            debug!("No {METRIC_NAME} datapoints for {repo_name}");
+            None
+        } else {
+            Some(datapoints.iter().filter_map(|d| d.sum()).sum::<f64>() as u64)
+        };
+        Ok(RepositoryPullMetrics {
+            window_days: self.window_days,
+            pull_count,
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A token bucket limiting how many requests per second are started, shared by every
+/// clone. Up to one second's worth of requests can be made in a burst.
+///
+/// Callers reserve a token and then sleep until it is due, so the lock is never held
+/// across an await and concurrent tasks are spaced out rather than serialized.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_second: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Panics if `per_second` is zero.
+    pub fn new(per_second: u32) -> Self {
+        assert!(
+            per_second > 0,
+            "RateLimiter needs a rate of at least 1 per second"
+        );
+        let per_second = f64::from(per_second);
+        Self {
+            per_second,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: per_second,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    /// Wait until another request may be made.
+    pub async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * self.per_second;
+            bucket.tokens = (bucket.tokens + refill).min(self.per_second) - 1.0;
+            bucket.updated = now;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.per_second)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_then_spaces_requests() {
+        let limiter = RateLimiter::new(4);
+        let start = Instant::now();
+        let mut acquired_at = vec![];
+        for _ in 0..6 {
+            limiter.acquire().await;
+            acquired_at.push(start.elapsed());
+        }
+        let ms = Duration::from_millis;
+        // A second's worth at once, then one every 1/rate.
+        assert_eq!(acquired_at, [ms(0), ms(0), ms(0), ms(0), ms(250), ms(500)]);
+
+        // Idling refills the bucket, but only up to one second's worth.
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let start = Instant::now();
+        let mut acquired_at = vec![];
+        for _ in 0..5 {
+            limiter.acquire().await;
+            acquired_at.push(start.elapsed());
+        }
+        assert_eq!(acquired_at, [ms(0), ms(0), ms(0), ms(0), ms(250)]);
+    }
+}
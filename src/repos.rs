@@ -1,20 +1,137 @@
+use crate::ecr_api::EcrApi;
+use crate::images::ImageFailure;
+use crate::metrics::DumpMetrics;
+use crate::pull_metrics::RepositoryPullMetrics;
+use crate::rate_limit::RateLimiter;
+use crate::retry::RetryPolicy;
+use crate::signing::SigningCoverage;
+use aws_sdk_ecr::operation::describe_repositories::DescribeRepositoriesInput;
+use aws_sdk_ecr::types::Repository;
 use aws_sdk_ecr::Client;
+use aws_smithy_types_convert::date_time::DateTimeExt;
+use chrono::{DateTime, Utc};
 use globset::GlobSet;
 use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use tracing::{debug, instrument};
 
 pub type RepositoryName = String;
 
-pub struct RepositoryLister {
-    client: Client,
+/// Per-repository rollup record, written ahead of a repository's images when a
+/// repository-level option (such as `--pull-metrics`) is enabled.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepositoryRecord {
+    pub repository: RepositorySummary,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepositorySummary {
+    pub repository_name: RepositoryName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub image_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pull_metrics: Option<RepositoryPullMetrics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<RepositoryEncryption>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RepositoryMetadata>,
+    /// The repository's resource tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing: Option<SigningCoverage>,
+    /// Only some of the repository's images were listed before an error.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// Manifests that `BatchGetImage` couldn't return.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<ImageFailure>,
+}
+
+/// How a repository's images are encrypted at rest: `AES256`, or `KMS` with the key ARN.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepositoryEncryption {
+    pub encryption_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kms_key: Option<String>,
+}
+
+impl RepositoryEncryption {
+    pub fn from_repository(repository: &Repository) -> Option<Self> {
+        let configuration = repository.encryption_configuration()?;
+        Some(Self {
+            encryption_type: configuration.encryption_type().to_string(),
+            kms_key: configuration.kms_key().map(str::to_string),
+        })
+    }
+}
+
+/// Repository settings from `DescribeRepositories`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepositoryMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository_arn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// `MUTABLE` or `IMMUTABLE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_tag_mutability: Option<String>,
+    pub scan_on_push: bool,
+    /// `AES256`, `KMS` or `KMS_DSSE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption_type: Option<String>,
+}
+
+impl RepositoryMetadata {
+    pub fn from_repository(repository: &Repository) -> Self {
+        Self {
+            repository_arn: repository.repository_arn().map(str::to_string),
+            created_at: repository
+                .created_at()
+                .and_then(|time| time.to_chrono_utc().ok()),
+            image_tag_mutability: repository
+                .image_tag_mutability()
+                .map(|mutability| mutability.as_str().to_string()),
+            scan_on_push: repository
+                .image_scanning_configuration()
+                .is_some_and(|configuration| configuration.scan_on_push()),
+            encryption_type: repository
+                .encryption_configuration()
+                .map(|configuration| configuration.encryption_type().as_str().to_string()),
+        }
+    }
+}
+
+pub struct RepositoryLister<C = Client> {
+    client: C,
     include_filter: Option<GlobSet>,
     exclude_filter: Option<GlobSet>,
     page_size: i32,
+    /// Sort repositories by name. Without this they are returned in the order
+    /// `DescribeRepositories` lists them, which is not guaranteed to be stable. Either
+    /// way, every page is listed before any are returned.
+    pub sort: bool,
+    /// The account whose registry to list, if not the caller's own.
+    pub registry_id: Option<String>,
+    pub retry: RetryPolicy,
+    /// Shared with the image fetchers, so listing counts against the same rate.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Counts each `DescribeRepositories` call, including retries.
+    pub metrics: Arc<DumpMetrics>,
+    /// Include repositories whose name contains a match. With both this and the include
+    /// globs set, matching either is enough.
+    pub include_regex: Option<Regex>,
+    /// Exclude repositories whose name contains a match, like the exclude globs.
+    pub exclude_regex: Option<Regex>,
 }
 
-impl RepositoryLister {
+impl<C: EcrApi> RepositoryLister<C> {
     pub fn new(
-        client: Client,
+        client: C,
         include_filter: Option<GlobSet>,
         exclude_filter: Option<GlobSet>,
     ) -> Self {
@@ -22,7 +139,7 @@ impl RepositoryLister {
     }
 
     pub fn new_with_page_size(
-        client: Client,
+        client: C,
         include_filter: Option<GlobSet>,
         exclude_filter: Option<GlobSet>,
         page_size: i32,
@@ -32,46 +149,180 @@ impl RepositoryLister {
             include_filter,
             exclude_filter,
             page_size,
+            sort: true,
+            registry_id: None,
+            retry: RetryPolicy::default(),
+            rate_limiter: None,
+            metrics: Arc::default(),
+            include_regex: None,
+            exclude_regex: None,
         }
     }
 
     #[instrument(name = "List repositories", skip_all)]
     pub async fn list(&self) -> anyhow::Result<Vec<RepositoryName>> {
-        let repositories: Result<Vec<_>, _> = self
-            .client
-            .describe_repositories()
-            .set_max_results(Some(self.page_size))
-            .into_paginator()
-            .items()
-            .send()
-            .collect()
-            .await;
-        let repositories = repositories?
+        Ok(self
+            .list_repositories()
+            .await?
             .into_iter()
             .map(|r| r.repository_name.unwrap())
-            .filter_map(|name| {
-                let has_filter = self.include_filter.is_some() || self.exclude_filter.is_some();
-                if !has_filter {
-                    return Some(name);
-                }
-                if let Some(include_filter) = &self.include_filter {
-                    if include_filter.is_match(&name) {
-                        debug!("Include filter matched {name} - including");
-                        return Some(name);
-                    }
-                }
-                if let Some(exclude_filter) = &self.exclude_filter {
-                    if !exclude_filter.is_match(&name) {
-                        debug!("Exclude filter did not match {name} - including");
-                        return Some(name);
+            .collect())
+    }
+
+    /// List the repositories matching the filters, keeping everything
+    /// `DescribeRepositories` returned about them.
+    #[instrument(name = "List repositories", skip_all)]
+    pub async fn list_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let mut repositories = vec![];
+        let mut next_token = None;
+        loop {
+            let input = DescribeRepositoriesInput::builder()
+                .set_registry_id(self.registry_id.clone())
+                .set_max_results(Some(self.page_size))
+                .set_next_token(next_token)
+                .build()?;
+            let page = self
+                .retry
+                .retry("DescribeRepositories", || async {
+                    if let Some(rate_limiter) = &self.rate_limiter {
+                        rate_limiter.acquire().await;
                     }
-                }
-                debug!("No filter match for {name}, skipping");
-                None
-            })
-            .sorted()
+                    self.metrics.add_api_call();
+                    self.client.describe_repositories(input.clone()).await
+                })
+                .await?;
+            repositories.extend(page.repositories.unwrap_or_default());
+            next_token = page.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+        let mut repositories = repositories
+            .into_iter()
+            .filter(|r| self.is_included(r.repository_name().unwrap()))
             .collect_vec();
+        if self.sort {
+            repositories.sort_by(|a, b| a.repository_name().cmp(&b.repository_name()));
+        }
 
         Ok(repositories)
     }
+
+    /// A repository must match one of the include filters, if any are set, and must not
+    /// match any exclude filter. Exclusion takes precedence.
+    fn is_included(&self, name: &str) -> bool {
+        if self.include_filter.is_some() || self.include_regex.is_some() {
+            let included = self
+                .include_filter
+                .as_ref()
+                .is_some_and(|filter| filter.is_match(name))
+                || self
+                    .include_regex
+                    .as_ref()
+                    .is_some_and(|regex| regex.is_match(name));
+            if !included {
+                debug!("Include filters did not match {name}, skipping");
+                return false;
+            }
+        }
+        let excluded = self
+            .exclude_filter
+            .as_ref()
+            .is_some_and(|filter| filter.is_match(name))
+            || self
+                .exclude_regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(name));
+        if excluded {
+            debug!("Exclude filter matched {name}, skipping");
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_ecr::FakeEcr;
+    use globset::{Glob, GlobSetBuilder};
+
+    fn globs(patterns: &[&str]) -> Option<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(Glob::new(pattern).unwrap());
+        }
+        Some(builder.build().unwrap())
+    }
+
+    fn included(lister: &RepositoryLister<FakeEcr>) -> Vec<&'static str> {
+        ["team-a/api", "team-a/web", "team-b/api", "legacy"]
+            .into_iter()
+            .filter(|name| lister.is_included(name))
+            .collect()
+    }
+
+    #[test]
+    fn includes_everything_without_filters() {
+        let lister = RepositoryLister::new(FakeEcr::default(), None, None);
+        assert_eq!(
+            included(&lister),
+            ["team-a/api", "team-a/web", "team-b/api", "legacy"]
+        );
+    }
+
+    #[test]
+    fn includes_only_matches() {
+        let lister = RepositoryLister::new(FakeEcr::default(), globs(&["team-a/*"]), None);
+        assert_eq!(included(&lister), ["team-a/api", "team-a/web"]);
+
+        let mut lister = RepositoryLister::new(FakeEcr::default(), None, None);
+        lister.include_regex = Some(Regex::new("api$").unwrap());
+        assert_eq!(included(&lister), ["team-a/api", "team-b/api"]);
+
+        // Matching either the globs or the regex is enough.
+        let mut lister = RepositoryLister::new(FakeEcr::default(), globs(&["legacy"]), None);
+        lister.include_regex = Some(Regex::new("^team-b/").unwrap());
+        assert_eq!(included(&lister), ["team-b/api", "legacy"]);
+    }
+
+    #[test]
+    fn excludes_matches() {
+        let lister = RepositoryLister::new(FakeEcr::default(), None, globs(&["*/api"]));
+        assert_eq!(included(&lister), ["team-a/web", "legacy"]);
+
+        let mut lister = RepositoryLister::new(FakeEcr::default(), None, None);
+        lister.exclude_regex = Some(Regex::new("^legacy$").unwrap());
+        assert_eq!(
+            included(&lister),
+            ["team-a/api", "team-a/web", "team-b/api"]
+        );
+    }
+
+    #[test]
+    fn exclusion_wins_over_inclusion() {
+        let mut lister =
+            RepositoryLister::new(FakeEcr::default(), globs(&["team-a/*"]), globs(&["*/web"]));
+        assert_eq!(included(&lister), ["team-a/api"]);
+
+        lister.exclude_regex = Some(Regex::new("api").unwrap());
+        assert!(included(&lister).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lists_every_page_sorted() {
+        let ecr = FakeEcr::default();
+        for name in ["web", "api", "worker", "batch", "cron"] {
+            ecr.add_repository(name);
+        }
+        ecr.throttle("DescribeRepositories", None);
+        let lister = RepositoryLister::new_with_page_size(ecr.clone(), None, None, 2);
+        assert_eq!(
+            lister.list().await.unwrap(),
+            ["api", "batch", "cron", "web", "worker"]
+        );
+        // One throttled call, then three pages.
+        assert_eq!(ecr.calls("DescribeRepositories"), 4);
+        assert_eq!(lister.metrics.snapshot().api_calls, 4);
+    }
 }
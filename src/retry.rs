@@ -0,0 +1,269 @@
+use aws_sdk_ecr::config::http::HttpResponse;
+use aws_sdk_ecr::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_ecr::primitives::{DateTime, DateTimeFormat};
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+const THROTTLING_CODES: &[&str] = &[
+    "ThrottlingException",
+    "Throttling",
+    "TooManyRequestsException",
+    "RequestLimitExceeded",
+];
+
+/// How much randomness is applied to each backoff delay, so that many repositories
+/// throttled at once don't all retry at the same moment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Jitter {
+    /// Anywhere between zero and the full delay.
+    #[default]
+    Full,
+    /// Half the delay, plus up to the other half at random.
+    Equal,
+    /// Exactly the exponential delay.
+    None,
+}
+
+/// How SDK calls are retried when AWS throttles them, fails with a server error, or the
+/// connection fails.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: Jitter,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(20),
+            jitter: Jitter::default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        match self.jitter {
+            Jitter::Full => delay.mul_f64(fastrand::f64()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(fastrand::f64()),
+            Jitter::None => delay,
+        }
+    }
+
+    /// Run `operation`, retrying throttled calls, server errors, connection failures and
+    /// timeouts. A `Retry-After` header on the throttled response is honoured (capped at
+    /// `max_delay`); otherwise the delay grows exponentially from `base_delay`, with
+    /// `jitter` applied.
+    pub async fn retry<T, E, F, Fut>(
+        &self,
+        name: &str,
+        operation: F,
+    ) -> Result<T, SdkError<E, HttpResponse>>
+    where
+        E: ProvideErrorMetadata,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, SdkError<E, HttpResponse>>>,
+    {
+        self.retry_when(name, operation, |e| {
+            is_retryable(e).then(|| (error_code(e), retry_after(e)))
+        })
+        .await
+    }
+
+    /// Run a plain HTTP request, such as a blob download, retrying connection errors,
+    /// timeouts, 429s and server errors the same way.
+    pub async fn retry_http<T, F, Fut>(&self, name: &str, operation: F) -> reqwest::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = reqwest::Result<T>>,
+    {
+        self.retry_when(name, operation, |e| match e.status() {
+            Some(status) if status.as_u16() == 429 || status.is_server_error() => {
+                Some((status.as_u16().to_string(), None))
+            }
+            Some(_) => None,
+            None => (e.is_connect() || e.is_timeout()).then(|| (e.to_string(), None)),
+        })
+        .await
+    }
+
+    /// `classify` returns the error's code and any delay the server asked for when it is
+    /// worth retrying.
+    async fn retry_when<T, E, F, Fut>(
+        &self,
+        name: &str,
+        mut operation: F,
+        classify: impl Fn(&E) -> Option<(String, Option<Duration>)>,
+    ) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Err(e) if attempt < self.max_retries => {
+                    let Some((code, retry_after)) = classify(&e) else {
+                        return Err(e);
+                    };
+                    let delay = retry_after
+                        .map(|delay| delay.min(self.max_delay))
+                        .unwrap_or_else(|| self.backoff(attempt));
+                    attempt += 1;
+                    warn!(
+                        "{name} failed ({code}), retry {attempt}/{} in {delay:?}",
+                        self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Throttling, server errors and transient failures to get a whole response are worth
+/// retrying; client errors such as access denied or a missing repository will fail the
+/// same way again.
+fn is_retryable<E: ProvideErrorMetadata>(error: &SdkError<E, HttpResponse>) -> bool {
+    matches!(
+        error,
+        SdkError::DispatchFailure(_) | SdkError::TimeoutError(_) | SdkError::ResponseError(_)
+    ) || error
+        .code()
+        .is_some_and(|code| THROTTLING_CODES.contains(&code))
+        || error.raw_response().is_some_and(|response| {
+            let status = response.status();
+            status.as_u16() == 429 || status.is_server_error()
+        })
+}
+
+fn error_code<E: ProvideErrorMetadata>(error: &SdkError<E, HttpResponse>) -> String {
+    match (error.code(), error.raw_response()) {
+        (Some(code), _) => code.to_string(),
+        (None, Some(response)) => response.status().as_u16().to_string(),
+        (None, None) => match error {
+            SdkError::DispatchFailure(_) => "dispatch failure".to_string(),
+            SdkError::TimeoutError(_) => "timeout".to_string(),
+            _ => "no response".to_string(),
+        },
+    }
+}
+
+/// `Retry-After` as either a number of seconds or an HTTP date.
+fn retry_after<E>(error: &SdkError<E, HttpResponse>) -> Option<Duration> {
+    let value = error.raw_response()?.headers().get("retry-after")?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::from_str(value, DateTimeFormat::HttpDate).ok()?;
+    let at = SystemTime::try_from(at).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecr_api::EcrApi;
+    use crate::fake_ecr::FakeEcr;
+    use aws_sdk_ecr::operation::describe_images::{DescribeImagesError, DescribeImagesInput};
+    use aws_smithy_runtime_api::http::StatusCode;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_retry_after_then_succeeds() {
+        let ecr = FakeEcr::default();
+        ecr.add_repository("app");
+        ecr.throttle("DescribeImages", Some(3));
+        let input = DescribeImagesInput::builder()
+            .repository_name("app")
+            .build()
+            .unwrap();
+
+        let started = Instant::now();
+        let mut attempts = 0;
+        let result = RetryPolicy::default()
+            .retry("DescribeImages", || {
+                attempts += 1;
+                ecr.describe_images(input.clone())
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+        assert_eq!(ecr.calls("DescribeImages"), 2);
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_dispatch_failures() {
+        let ecr = FakeEcr::default();
+        ecr.add_repository("app");
+        ecr.fail_dispatch("DescribeImages");
+        let input = DescribeImagesInput::builder()
+            .repository_name("app")
+            .build()
+            .unwrap();
+
+        let result = RetryPolicy::default()
+            .retry("DescribeImages", || ecr.describe_images(input.clone()))
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(ecr.calls("DescribeImages"), 2);
+    }
+
+    #[test]
+    fn parses_retry_after_dates() {
+        let mut response = HttpResponse::new(
+            StatusCode::try_from(503).unwrap(),
+            aws_smithy_types::body::SdkBody::empty(),
+        );
+        let at = DateTime::from(SystemTime::now() + Duration::from_secs(120));
+        response
+            .headers_mut()
+            .insert("retry-after", at.fmt(DateTimeFormat::HttpDate).unwrap());
+        let error: SdkError<DescribeImagesError, _> =
+            SdkError::response_error(std::io::Error::other("unused"), response);
+        let delay = retry_after(&error).unwrap();
+        assert!(delay > Duration::from_secs(115) && delay <= Duration::from_secs(120));
+
+        let mut response = HttpResponse::new(
+            StatusCode::try_from(503).unwrap(),
+            aws_smithy_types::body::SdkBody::empty(),
+        );
+        response
+            .headers_mut()
+            .insert("retry-after", "Sun, 06 Nov 1994 08:49:37 GMT");
+        let error: SdkError<DescribeImagesError, _> =
+            SdkError::response_error(std::io::Error::other("unused"), response);
+        assert_eq!(retry_after(&error), Some(Duration::ZERO));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_retry_after_at_max_delay() {
+        let ecr = FakeEcr::default();
+        ecr.add_repository("app");
+        ecr.throttle("DescribeImages", Some(600));
+        let input = DescribeImagesInput::builder()
+            .repository_name("app")
+            .build()
+            .unwrap();
+
+        let started = Instant::now();
+        let policy = RetryPolicy::default();
+        let result = policy
+            .retry("DescribeImages", || ecr.describe_images(input.clone()))
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(started.elapsed(), policy.max_delay);
+    }
+}
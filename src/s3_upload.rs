@@ -0,0 +1,233 @@
+use anyhow::Context;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{ready, Context as TaskContext, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, DuplexStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+const S3_SCHEME: &str = "s3://";
+
+/// Size of each uploaded part. S3 requires at least 5 MiB for all but the last.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Uploads that were dropped before completing and are being aborted.
+static ABORTING: Mutex<Vec<JoinHandle<anyhow::Result<()>>>> = Mutex::new(vec![]);
+
+/// The bucket and key of an `s3://bucket/key` output.
+pub fn s3_location(path: &Path) -> Option<(&str, &str)> {
+    path.to_str()?
+        .strip_prefix(S3_SCHEME)?
+        .split_once('/')
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+}
+
+/// Streams everything written to it into an S3 object, uploaded in parts as it is
+/// written. The object only appears once the writer is shut down; if it's dropped
+/// first, or a part fails, the multipart upload is aborted.
+pub struct S3Writer {
+    pipe: DuplexStream,
+    complete: Option<oneshot::Sender<()>>,
+    upload: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl S3Writer {
+    pub fn new(client: Client, bucket: &str, key: &str) -> Self {
+        let (pipe, reader) = tokio::io::duplex(PART_SIZE);
+        let (complete, completed) = oneshot::channel();
+        let upload = Upload {
+            client,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        };
+        Self {
+            pipe,
+            complete: Some(complete),
+            upload: Some(tokio::spawn(upload.run(reader, completed))),
+        }
+    }
+}
+
+impl AsyncWrite for S3Writer {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        if let Some(complete) = self.complete.take() {
+            let _ = complete.send(());
+        }
+        ready!(Pin::new(&mut self.pipe).poll_shutdown(cx))?;
+        let Some(upload) = &mut self.upload else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = ready!(Pin::new(upload).poll(cx));
+        self.upload = None;
+        Poll::Ready(match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(io::Error::other(format!("{e:#}"))),
+            Err(e) => Err(io::Error::other(e)),
+        })
+    }
+}
+
+impl Drop for S3Writer {
+    fn drop(&mut self) {
+        if let Some(upload) = self.upload.take() {
+            ABORTING.lock().unwrap().push(upload);
+        }
+    }
+}
+
+/// Wait for uploads whose writers were dropped to be aborted, so no parts are left
+/// behind when the process exits.
+pub async fn wait_for_aborted_uploads() {
+    let uploads = std::mem::take(&mut *ABORTING.lock().unwrap());
+    for upload in uploads {
+        match upload.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("{e:#}"),
+            Err(e) => warn!("S3 upload task failed: {e}"),
+        }
+    }
+}
+
+struct Upload {
+    client: Client,
+    bucket: String,
+    key: String,
+}
+
+impl Upload {
+    /// Upload everything read from `reader`. Output smaller than one part is written
+    /// with a single `PutObject` once complete, otherwise a multipart upload is started
+    /// with the first part.
+    async fn run(
+        self,
+        mut reader: impl AsyncRead + Unpin,
+        completed: oneshot::Receiver<()>,
+    ) -> anyhow::Result<()> {
+        let mut part = read_part(&mut reader).await?;
+        if part.len() < PART_SIZE {
+            completed
+                .await
+                .context("S3 output was not completed, nothing uploaded")?;
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .body(ByteStream::from(part))
+                .send()
+                .await
+                .with_context(|| format!("Uploading s3://{}/{}", self.bucket, self.key))?;
+            return Ok(());
+        }
+
+        let upload_id = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .send()
+            .await
+            .with_context(|| format!("Starting upload to s3://{}/{}", self.bucket, self.key))?
+            .upload_id
+            .context("CreateMultipartUpload returned no upload ID")?;
+        let result = async {
+            let mut parts = vec![];
+            while !part.is_empty() {
+                parts.push(
+                    self.upload_part(&upload_id, parts.len() as i32 + 1, part)
+                        .await?,
+                );
+                part = read_part(&mut reader).await?;
+            }
+            completed.await.context("S3 output was not completed")?;
+            self.client
+                .complete_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(&upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .context("Completing multipart upload")?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            info!(
+                "Aborting upload to s3://{}/{}: {e:#}",
+                self.bucket, self.key
+            );
+            self.client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(&upload_id)
+                .send()
+                .await
+                .with_context(|| {
+                    format!(
+                        "Aborting upload {upload_id} to s3://{}/{}",
+                        self.bucket, self.key
+                    )
+                })?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn upload_part(
+        &self,
+        upload_id: &str,
+        part_number: i32,
+        body: Vec<u8>,
+    ) -> anyhow::Result<CompletedPart> {
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .with_context(|| format!("Uploading part {part_number}"))?;
+        Ok(CompletedPart::builder()
+            .part_number(part_number)
+            .set_e_tag(output.e_tag)
+            .build())
+    }
+}
+
+/// Read up to a full part, returning less only at the end of the output.
+async fn read_part(reader: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Vec<u8>> {
+    let mut part = Vec::with_capacity(PART_SIZE);
+    reader
+        .take(PART_SIZE as u64)
+        .read_to_end(&mut part)
+        .await
+        .context("Reading S3 output")?;
+    Ok(part)
+}
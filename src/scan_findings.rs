@@ -0,0 +1,54 @@
+use aws_sdk_ecr::operation::describe_image_scan_findings::DescribeImageScanFindingsOutput;
+use aws_smithy_types_convert::date_time::DateTimeExt;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Status recorded for an image ECR has never scanned.
+pub const NOT_SCANNED: &str = "not_scanned";
+
+/// The outcome of an image's latest ECR scan, basic or enhanced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanSummary {
+    /// ECR's scan status, lowercased (`complete`, `in_progress`, `failed`, ...), or
+    /// `not_scanned`.
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Number of findings per severity, such as `CRITICAL` or `HIGH`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub severity_counts: BTreeMap<String, i32>,
+}
+
+impl ScanSummary {
+    pub fn not_scanned() -> Self {
+        Self {
+            status: NOT_SCANNED.to_string(),
+            completed_at: None,
+            severity_counts: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<DescribeImageScanFindingsOutput> for ScanSummary {
+    fn from(output: DescribeImageScanFindingsOutput) -> Self {
+        let status = output
+            .image_scan_status
+            .and_then(|status| status.status)
+            .map_or_else(|| "unknown".to_string(), |s| s.as_str().to_lowercase());
+        let findings = output.image_scan_findings;
+        Self {
+            status,
+            completed_at: findings
+                .as_ref()
+                .and_then(|f| f.image_scan_completed_at)
+                .and_then(|time| time.to_chrono_utc().ok()),
+            severity_counts: findings
+                .and_then(|f| f.finding_severity_counts)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(severity, count)| (severity.as_str().to_string(), count))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::images::ImageWithManifests;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// How many of a repository's images have cosign signatures and attestations.
+///
+/// Detected from cosign's tag scheme: an image `sha256:<hex>` is signed if the repository
+/// also has a `sha256-<hex>.sig` tag, attested with `.att` and has an SBOM with `.sbom`.
+/// The artifact images themselves are not counted in `image_count`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SigningCoverage {
+    pub image_count: usize,
+    pub signed: usize,
+    pub attested: usize,
+    pub with_sbom: usize,
+}
+
+impl SigningCoverage {
+    pub fn from_images(images: &[ImageWithManifests]) -> Self {
+        let mut signatures = HashSet::new();
+        let mut attestations = HashSet::new();
+        let mut sboms = HashSet::new();
+        let mut subjects = vec![];
+        for image in images {
+            let mut is_artifact = false;
+            for tag in &image.image.image_tags {
+                let Some((subject, suffix)) = tag.rsplit_once('.') else {
+                    continue;
+                };
+                let Some(hex) = subject.strip_prefix("sha256-") else {
+                    continue;
+                };
+                let set = match suffix {
+                    "sig" => &mut signatures,
+                    "att" => &mut attestations,
+                    "sbom" => &mut sboms,
+                    _ => continue,
+                };
+                set.insert(format!("sha256:{hex}"));
+                is_artifact = true;
+            }
+            if !is_artifact {
+                subjects.push(image.image.manifest_digest.as_str());
+            }
+        }
+        let count = |set: &HashSet<String>| {
+            subjects
+                .iter()
+                .filter(|digest| set.contains(**digest))
+                .count()
+        };
+        Self {
+            image_count: subjects.len(),
+            signed: count(&signatures),
+            attested: count(&attestations),
+            with_sbom: count(&sboms),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use ecr_dump::images::{ApiCallCounts, ImageWithManifests, ManifestType};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// Totals for a run, printed to stderr when it finishes and optionally written as JSON.
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub repositories: usize,
+    /// Repositories with no images once fetched and filtered.
+    pub empty_repositories: usize,
+    pub images: usize,
+    pub manifests: usize,
+    pub images_by_type: BTreeMap<ManifestType, usize>,
+    /// Images kept without manifests because their media type isn't supported.
+    pub unknown_media_types: usize,
+    /// Manifests `BatchGetImage` could not return.
+    pub missing_manifests: usize,
+    /// Manifests that failed `--strict-manifests` schema validation.
+    pub invalid_manifests: usize,
+    /// Images not written because they were already in the `--baseline` dump.
+    pub unchanged_images: usize,
+    /// ECR calls made across every repository, including retries.
+    pub api_calls: ApiCallCounts,
+    /// The dump was stopped early by Ctrl-C or `--timeout`, so only covers some
+    /// repositories.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    pub duration_seconds: f64,
+}
+
+impl RunSummary {
+    pub fn add_image(&mut self, image: &ImageWithManifests) {
+        self.images += 1;
+        self.manifests += image.manifests.len();
+        *self
+            .images_by_type
+            .entry(image.image.manifest_type)
+            .or_default() += 1;
+        if image.image.manifest_type == ManifestType::Unknown {
+            self.unknown_media_types += 1;
+        }
+    }
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Repositories:        {}", self.repositories)?;
+        if self.empty_repositories > 0 {
+            writeln!(f, "  Empty:             {}", self.empty_repositories)?;
+        }
+        writeln!(f, "Images:              {}", self.images)?;
+        for (manifest_type, count) in &self.images_by_type {
+            writeln!(f, "  {:<18} {count}", format!("{manifest_type}:"))?;
+        }
+        writeln!(f, "Manifests:           {}", self.manifests)?;
+        writeln!(f, "Unknown media types: {}", self.unknown_media_types)?;
+        writeln!(f, "Missing manifests:   {}", self.missing_manifests)?;
+        if self.invalid_manifests > 0 {
+            writeln!(f, "Invalid manifests:   {}", self.invalid_manifests)?;
+        }
+        if self.unchanged_images > 0 {
+            writeln!(f, "Unchanged images:    {}", self.unchanged_images)?;
+        }
+        writeln!(f, "API calls:           {}", self.api_calls.total())?;
+        if self.interrupted {
+            writeln!(f, "Interrupted:         yes")?;
+        }
+        write!(f, "Duration:            {:.1}s", self.duration_seconds)
+    }
+}
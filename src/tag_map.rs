@@ -0,0 +1,81 @@
+use clap::ValueEnum;
+use ecr_dump::images::ImageWithManifests;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// How `--tag-map` represents images that have no tags.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum UntaggedImages {
+    /// Leave them out: the map is tag to digest.
+    #[default]
+    Omit,
+    /// List their digests under an `"<untagged>"` key.
+    Include,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct RepositoryTags {
+    #[serde(flatten)]
+    tags: BTreeMap<String, String>,
+    #[serde(rename = "<untagged>", skip_serializing_if = "Vec::is_empty")]
+    untagged: Vec<String>,
+}
+
+/// A `{repository: {tag: digest}}` index of every dumped image, written as a sidecar
+/// once the dump completes. Images recorded with a region, as with `--all-regions`, are
+/// nested under it instead: `{region: {repository: {tag: digest}}}`, so same-named
+/// repositories in different regions are kept apart.
+#[derive(Debug, Default)]
+pub struct TagMap {
+    repositories: BTreeMap<(Option<String>, String), RepositoryTags>,
+    untagged: UntaggedImages,
+}
+
+impl TagMap {
+    pub fn new(untagged: UntaggedImages) -> Self {
+        Self {
+            repositories: BTreeMap::new(),
+            untagged,
+        }
+    }
+
+    pub fn add(&mut self, image: &ImageWithManifests) {
+        let region = image.region.clone();
+        let image = &image.image;
+        if image.image_tags.is_empty() && self.untagged == UntaggedImages::Omit {
+            return;
+        }
+        let entry = self
+            .repositories
+            .entry((region, image.repository_name.clone()))
+            .or_default();
+        if image.image_tags.is_empty() {
+            entry.untagged.push(image.manifest_digest.clone());
+        }
+        for tag in &image.image_tags {
+            entry
+                .tags
+                .insert(tag.clone(), image.manifest_digest.clone());
+        }
+    }
+}
+
+impl Serialize for TagMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.repositories.keys().all(|(region, _)| region.is_none()) {
+            return serializer.collect_map(
+                self.repositories
+                    .iter()
+                    .map(|((_, repository), tags)| (repository, tags)),
+            );
+        }
+        let mut regions: BTreeMap<&str, BTreeMap<&str, &RepositoryTags>> = BTreeMap::new();
+        for ((region, repository), tags) in &self.repositories {
+            regions
+                .entry(region.as_deref().unwrap_or_default())
+                .or_default()
+                .insert(repository, tags);
+        }
+        regions.serialize(serializer)
+    }
+}
@@ -0,0 +1,156 @@
+use crate::layer_stats::LayerStatsRecord;
+use crate::output;
+use anyhow::{bail, Context};
+use ecr_dump::images::{ImageWithManifests, SCHEMA_VERSION};
+use ecr_dump::repos::RepositoryRecord;
+use serde_json::Value;
+use std::path::Path;
+use tracing::{error, info, instrument};
+
+/// Stop printing individual errors after this many, to keep the report readable.
+const MAX_REPORTED_ERRORS: usize = 20;
+
+/// Check that every record of a dump is a well-formed image or repository record, with
+/// image records in the current `schema_version`. Both JSONL and `--output-format json`
+/// dumps are accepted.
+///
+/// Files ending in `.gz` or `.zst` are decompressed first, including `--framed-gzip`
+/// output. Projected output (`--fields`, `--oci-refs`) is not a full record and fails
+/// validation.
+#[instrument(skip_all, fields(file = %path.display()))]
+pub fn validate_dump(path: &Path) -> anyhow::Result<()> {
+    let mut images = 0;
+    let mut repositories = 0;
+    let mut errors = 0;
+    output::read_records(path, |number, record| {
+        match record.and_then(validate_record) {
+            Ok(RecordKind::Image) => images += 1,
+            Ok(RecordKind::Repository) => repositories += 1,
+            Ok(RecordKind::LayerStats) => {}
+            Err(e) => {
+                errors += 1;
+                if errors <= MAX_REPORTED_ERRORS {
+                    error!("Record {number}: {e:#}");
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    info!("{images} image records, {repositories} repository records, {errors} malformed");
+    if errors > 0 {
+        bail!("{} contains {errors} malformed records", path.display());
+    }
+    Ok(())
+}
+
+enum RecordKind {
+    Image,
+    Repository,
+    LayerStats,
+}
+
+fn validate_record(value: Value) -> anyhow::Result<RecordKind> {
+    if value.get("repository").is_some() {
+        serde_json::from_value::<RepositoryRecord>(value).context("Invalid repository record")?;
+        Ok(RecordKind::Repository)
+    } else if value.get("layer_stats").is_some() {
+        serde_json::from_value::<LayerStatsRecord>(value).context("Invalid layer stats record")?;
+        Ok(RecordKind::LayerStats)
+    } else {
+        match value.get("schema_version") {
+            None => bail!("Image record has no schema_version"),
+            Some(version) if version.as_u64() != Some(SCHEMA_VERSION.into()) => {
+                bail!("Unsupported schema_version {version}, expected {SCHEMA_VERSION}")
+            }
+            Some(_) => {}
+        }
+        serde_json::from_value::<ImageWithManifests>(value).context("Invalid image record")?;
+        Ok(RecordKind::Image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ecr_dump::images::{ManifestType, RepositoryImage};
+    use std::io::Write;
+
+    fn image_record() -> String {
+        let image = RepositoryImage {
+            repository_name: "app".to_string(),
+            manifest_digest: "sha256:abc".to_string(),
+            manifest_type: ManifestType::Image,
+            image_tags: vec!["latest".to_string()],
+            image_pushed_at: chrono::Utc::now(),
+            manifest_media_type: String::new(),
+            image_size_in_bytes: None,
+            last_recorded_pull_time: None,
+        };
+        serde_json::to_string(&ImageWithManifests::new(image, vec![])).unwrap()
+    }
+
+    #[test]
+    fn checks_schema_version() {
+        let mut value: Value = serde_json::from_str(&image_record()).unwrap();
+        assert!(matches!(
+            validate_record(value.clone()),
+            Ok(RecordKind::Image)
+        ));
+
+        value["schema_version"] = (SCHEMA_VERSION + 1).into();
+        assert!(validate_record(value.clone()).is_err());
+        value.as_object_mut().unwrap().remove("schema_version");
+        assert!(validate_record(value).is_err());
+    }
+
+    #[test]
+    fn decompresses_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = format!("{}\n{}\n", image_record(), image_record());
+
+        let plain = dir.path().join("dump.jsonl");
+        std::fs::write(&plain, &lines).unwrap();
+        validate_dump(&plain).unwrap();
+
+        // Two gzip members, as written by --framed-gzip.
+        let gzip = dir.path().join("dump.jsonl.gz");
+        let mut data = vec![];
+        for line in lines.split_inclusive('\n') {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+            encoder.write_all(line.as_bytes()).unwrap();
+            data.extend(encoder.finish().unwrap());
+        }
+        std::fs::write(&gzip, data).unwrap();
+        validate_dump(&gzip).unwrap();
+
+        let zstd = dir.path().join("dump.jsonl.zst");
+        std::fs::write(&zstd, zstd::encode_all(lines.as_bytes(), 3).unwrap()).unwrap();
+        validate_dump(&zstd).unwrap();
+
+        // Not actually compressed.
+        std::fs::write(&zstd, &lines).unwrap();
+        assert!(validate_dump(&zstd).is_err());
+    }
+
+    #[test]
+    fn reads_json_arrays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.json");
+        let array = format!("[\n{},\n{}\n]\n", image_record(), image_record());
+        std::fs::write(&path, array).unwrap();
+        validate_dump(&path).unwrap();
+
+        std::fs::write(&path, "[]\n").unwrap();
+        validate_dump(&path).unwrap();
+
+        let mut value: Value = serde_json::from_str(&image_record()).unwrap();
+        value.as_object_mut().unwrap().remove("schema_version");
+        std::fs::write(&path, format!("[\n{value}\n]\n")).unwrap();
+        assert!(validate_dump(&path).is_err());
+
+        // Cut short.
+        std::fs::write(&path, format!("[\n{},\n", image_record())).unwrap();
+        assert!(validate_dump(&path).is_err());
+    }
+}
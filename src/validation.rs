@@ -0,0 +1,100 @@
+use serde_json::{Map, Value};
+
+const MANIFEST_FIELDS: &[&str] = &[
+    "schemaVersion",
+    "mediaType",
+    "artifactType",
+    "config",
+    "layers",
+    "subject",
+    "annotations",
+];
+const REQUIRED_MANIFEST_FIELDS: &[&str] = &["schemaVersion", "config", "layers"];
+
+const DESCRIPTOR_FIELDS: &[&str] = &[
+    "mediaType",
+    "artifactType",
+    "digest",
+    "size",
+    "urls",
+    "annotations",
+    "data",
+    "platform",
+];
+const REQUIRED_DESCRIPTOR_FIELDS: &[&str] = &["mediaType", "digest", "size"];
+
+/// Structurally check a raw image manifest against the OCI image manifest schema.
+///
+/// `serde` happily ignores unknown fields, so this works on the raw JSON and reports
+/// every unexpected or missing field rather than stopping at the first one.
+pub fn validate_image_manifest(raw: &str) -> Vec<String> {
+    let value: Value = match serde_json::from_str(raw) {
+        Ok(value) => value,
+        Err(e) => return vec![format!("invalid JSON: {e}")],
+    };
+    let Some(manifest) = value.as_object() else {
+        return vec!["manifest is not a JSON object".to_string()];
+    };
+
+    let mut issues = vec![];
+    check_fields(
+        "manifest",
+        manifest,
+        MANIFEST_FIELDS,
+        REQUIRED_MANIFEST_FIELDS,
+        &mut issues,
+    );
+    if let Some(version) = manifest.get("schemaVersion") {
+        if version.as_u64() != Some(2) {
+            issues.push(format!("manifest: schemaVersion is {version}, expected 2"));
+        }
+    }
+    if let Some(config) = manifest.get("config") {
+        check_descriptor("config", config, &mut issues);
+    }
+    match manifest.get("layers") {
+        Some(Value::Array(layers)) => {
+            for (idx, layer) in layers.iter().enumerate() {
+                check_descriptor(&format!("layers[{idx}]"), layer, &mut issues);
+            }
+        }
+        Some(_) => issues.push("manifest: layers is not an array".to_string()),
+        None => {}
+    }
+    if let Some(subject) = manifest.get("subject") {
+        check_descriptor("subject", subject, &mut issues);
+    }
+    issues
+}
+
+fn check_descriptor(path: &str, value: &Value, issues: &mut Vec<String>) {
+    match value.as_object() {
+        Some(descriptor) => check_fields(
+            path,
+            descriptor,
+            DESCRIPTOR_FIELDS,
+            REQUIRED_DESCRIPTOR_FIELDS,
+            issues,
+        ),
+        None => issues.push(format!("{path}: descriptor is not an object")),
+    }
+}
+
+fn check_fields(
+    path: &str,
+    object: &Map<String, Value>,
+    known: &[&str],
+    required: &[&str],
+    issues: &mut Vec<String>,
+) {
+    for key in object.keys() {
+        if !known.contains(&key.as_str()) {
+            issues.push(format!("{path}: unexpected field {key:?}"));
+        }
+    }
+    for key in required {
+        if !object.contains_key(*key) {
+            issues.push(format!("{path}: missing required field {key:?}"));
+        }
+    }
+}